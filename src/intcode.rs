@@ -1,4 +1,145 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+pub mod ascii;
+pub mod asm;
+pub mod bigint;
+pub mod cfg;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod history;
+pub mod io;
+pub mod isa;
+pub mod limits;
+pub mod lint;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod search;
+pub mod snapshot;
+pub mod threaded;
+pub mod trace;
+pub mod translate;
+pub mod watch;
+pub mod wide;
+
+pub use self::ascii::Ascii;
+pub use self::asm::assemble;
+pub use self::coverage::Coverage;
+pub use self::debugger::Debugger;
+pub use self::history::HistoryEntry;
+pub use self::disasm::disassemble;
+pub use self::io::{InputSource, OutputSink};
+pub use self::isa::{Effect, Execute, FeatureLevel, Instruction, InstructionSet};
+pub use self::lint::{lint, Diagnostic};
+pub use self::memory::{Memory, PagedMemory};
+pub use self::profile::Profile;
+pub use self::trace::TraceEvent;
+pub use self::watch::{Access, WatchHit, Watchpoint};
+
+/// Errors that can occur while loading or running an Intcode program.
 #[derive(Debug, Clone, PartialEq)]
+pub enum IntcodeError {
+    /// The instruction at `pc` has an opcode we don't recognise.
+    UnknownOpcode { pc: i64, opcode: i64 },
+    /// The instruction at `pc` has an opcode that only came along after the
+    /// feature level being run at.
+    UnsupportedOpcode { pc: i64, opcode: i64, level: FeatureLevel },
+    /// The instruction at `pc` has a parameter mode digit we don't recognise.
+    InvalidParamMode { pc: i64, opcode: i64, mode: i64 },
+    /// The instruction at `pc` has a parameter mode that only came along after
+    /// the feature level being run at.
+    UnsupportedParamMode { pc: i64, opcode: i64, mode: i64, level: FeatureLevel },
+    /// The instruction at `pc` tries to write to an immediate-mode parameter.
+    WriteInImmediateMode { pc: i64, opcode: i64 },
+    /// The instruction at `pc` stored a value or took input, but its
+    /// definition doesn't say which parameter to write to.
+    NoWriteParameter { pc: i64, opcode: i64 },
+    /// The instruction at `pc` tried to access a negative memory address.
+    NegativeAddress { pc: i64, address: i64 },
+    /// The instruction at `pc` wanted input, but there wasn't any.
+    InputExhausted { pc: i64 },
+    /// An arithmetic result at `pc` didn't fit in a cell, or a value used as
+    /// an address, jump target or instruction didn't fit in an i64.
+    Overflow { pc: i64 },
+    /// The program was still running at `pc` after its budget of `steps`
+    /// instructions.
+    StepLimit { pc: i64, steps: u64 },
+    /// The program was still running at `pc` when its deadline passed, after
+    /// `steps` instructions.
+    Timeout { pc: i64, steps: u64 },
+    /// The machine got back to exactly the same state it was in `period`
+    /// instructions before, with no input or output in between, so it will
+    /// never halt.
+    InfiniteLoop { pc: i64, period: u64 },
+    /// A saved snapshot couldn't be loaded.
+    InvalidSnapshot(String),
+    /// The program text couldn't be parsed; `position` is the index of the
+    /// offending entry.
+    ParseError { position: usize, entry: String },
+    /// The file `name` couldn't be read.
+    Io { name: String, message: String },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {} at pc {}", opcode, pc)
+            }
+            IntcodeError::UnsupportedOpcode { pc, opcode, level } => {
+                write!(f, "opcode {} at pc {} isn't supported at the {} feature level", opcode, pc, level)
+            }
+            IntcodeError::InvalidParamMode { pc, opcode, mode } => {
+                write!(f, "invalid parameter mode {} in {} at pc {}", mode, opcode, pc)
+            }
+            IntcodeError::UnsupportedParamMode { pc, opcode, mode, level } => write!(
+                f,
+                "parameter mode {} in {} at pc {} isn't supported at the {} feature level",
+                mode, opcode, pc, level
+            ),
+            IntcodeError::WriteInImmediateMode { pc, opcode } => {
+                write!(f, "write to immediate-mode parameter in {} at pc {}", opcode, pc)
+            }
+            IntcodeError::NoWriteParameter { pc, opcode } => {
+                write!(f, "{} at pc {} has nowhere to write its result", opcode, pc)
+            }
+            IntcodeError::NegativeAddress { pc, address } => {
+                write!(f, "access to negative address {} at pc {}", address, pc)
+            }
+            IntcodeError::InputExhausted { pc } => write!(f, "input exhausted at pc {}", pc),
+            IntcodeError::Overflow { pc } => write!(f, "overflow at pc {}", pc),
+            IntcodeError::StepLimit { pc, steps } => {
+                write!(f, "still running at pc {} after {} instructions", pc, steps)
+            }
+            IntcodeError::Timeout { pc, steps } => {
+                write!(f, "timed out at pc {} after {} instructions", pc, steps)
+            }
+            IntcodeError::InfiniteLoop { pc, period } => {
+                write!(f, "infinite loop at pc {}, repeating every {} instructions", pc, period)
+            }
+            IntcodeError::InvalidSnapshot(message) => write!(f, "invalid snapshot: {}", message),
+            IntcodeError::ParseError { position, entry } => {
+                write!(f, "invalid entry {:?} at position {}", entry, position)
+            }
+            IntcodeError::Io { name, message } => write!(f, "unable to read {}: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+/// Reads a whole file, for loading programs and snapshots.
+fn read_file(name: &str) -> Result<String, IntcodeError> {
+    std::fs::read_to_string(name).map_err(|e| IntcodeError::Io {
+        name: name.to_string(),
+        message: e.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Opcode {
     Add,         // 1
     Multiply,    // 2
@@ -10,9 +151,10 @@ enum Opcode {
     Equals,      // 8
     UpdateBase,  // 9
     Halt,        // 99
+    Custom,      // added by a dialect; see `isa`
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum ParamMode {
     Position,  // 0
     Immediate, // 1
@@ -26,321 +168,737 @@ pub enum StepResult {
     Halt,
     Continue,
     Output,
+    NeedInput,
+    Watchpoint(WatchHit),
+}
+
+// Instructions take at most three parameters, so decoding fills in fixed-size
+// arrays rather than allocating. Slots past num_params are unused.
+const MAX_PARAMS: usize = 3;
+
+/// Offsets an address (from the pc or relative base), failing with Overflow
+/// for the instruction at `pc` rather than wrapping or panicking.
+fn offset_address(address: i64, offset: i64, pc: i64) -> Result<i64, IntcodeError> {
+    address.checked_add(offset).ok_or(IntcodeError::Overflow { pc })
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct Operation {
+    // Just what running the instruction needs, to keep the decode cache
+    // small; the rest can be looked up in the instruction set by code.
+    pub code: i64,
+    pub execute: Execute,
     pub opcode: Opcode,
     pub num_params: i64,
-    pub param_modes: Vec<ParamMode>,
+    modes: [ParamMode; MAX_PARAMS],
+}
+
+/// A jump, as far as it can be made out without running it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StaticJump {
+    /// The mode and value of the parameter giving the target.
+    pub mode: ParamMode,
+    pub target: i64,
+    /// Whether the jump is taken, if its condition is an immediate.
+    pub taken: Option<bool>,
+}
+
+// Decoded instructions are cached for addresses below this; code lives at the
+// start of memory, so there's no need to cover the whole address space.
+const DECODE_CACHE_LIMIT: usize = 1 << 16;
+
+/// How a machine decodes its instructions. Only worth changing to compare
+/// them, as the `bench` tool does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoder {
+    /// Decodes each instruction every time it runs, collecting its modes into
+    /// a Vec first, as the VM used to.
+    Allocating,
+    /// Decodes each instruction every time it runs, without allocating.
+    Fresh,
+    /// Looks instructions up in a table decoded once from the image, which
+    /// clones share. The default.
+    Cached,
 }
 
-/// Parses an integer representing an opcode into an Opcode and vector of
-/// ParamModes.
-fn parse_opcode(input: i64) -> (Opcode, Vec<ParamMode>) {
+/// Parses a single parameter mode digit.
+fn parse_mode(input: i64, digit: i64, pc: i64) -> Result<ParamMode, IntcodeError> {
+    match digit {
+        0 => Ok(ParamMode::Position),
+        1 => Ok(ParamMode::Immediate),
+        2 => Ok(ParamMode::Relative),
+        mode => Err(IntcodeError::InvalidParamMode { pc, opcode: input, mode }),
+    }
+}
+
+/// Parses an integer representing an opcode into the Instruction it stands
+/// for in `isa`, and its ParamModes. The pc is only used for error reporting.
+fn parse_opcode(
+    input: i64,
+    pc: i64,
+    isa: &InstructionSet,
+) -> Result<(Instruction, [ParamMode; MAX_PARAMS]), IntcodeError> {
     debug!("input: {}", input);
 
-    let opcode = match input % 100 {
-        1 => Opcode::Add,
-        2 => Opcode::Multiply,
-        3 => Opcode::StoreInput,
-        4 => Opcode::PushOutput,
-        5 => Opcode::JumpIfTrue,
-        6 => Opcode::JumpIfFalse,
-        7 => Opcode::LessThan,
-        8 => Opcode::Equals,
-        9 => Opcode::UpdateBase,
-        99 => Opcode::Halt,
-        _ => panic!("Unsupported opcode {}!", input),
+    let level = isa.level();
+    let instruction = match isa.get(input % 100) {
+        Some(instruction) => *instruction,
+        // Something a later puzzle added, rather than garbage.
+        None if level < FeatureLevel::Day9 && isa::standard_set().get(input % 100).is_some() => {
+            return Err(IntcodeError::UnsupportedOpcode { pc, opcode: input, level })
+        }
+        None => return Err(IntcodeError::UnknownOpcode { pc, opcode: input }),
     };
-    debug!("opcode: {:?}", opcode);
+    debug!("instruction: {}", instruction.mnemonic);
 
-    let param1 = match (input / 100) % 10 {
-        1 => ParamMode::Immediate,
-        0 => ParamMode::Position,
-        2 => ParamMode::Relative,
-        _ => unreachable!()
-    };
-    let param2 = match (input / 1000) % 10 {
-        1 => ParamMode::Immediate,
-        0 => ParamMode::Position,
-        2 => ParamMode::Relative,
-        _ => unreachable!()
-    };
-    let param3 = match (input / 10000) % 10 {
-        1 => ParamMode::Immediate,
-        0 => ParamMode::Position,
-        2 => ParamMode::Relative,
-        _ => unreachable!()
-    };
+    let digits = [(input / 100) % 10, (input / 1000) % 10, (input / 10000) % 10];
+    let param1 = parse_mode(input, digits[0], pc)?;
+    let param2 = parse_mode(input, digits[1], pc)?;
+    let param3 = parse_mode(input, digits[2], pc)?;
     debug!("param1: {:?}, param2: {:?}, param3: {:?}", param1, param2, param3);
 
-    let param_modes = match opcode {
-        Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => {
-            // Params 1 and 2 can be Position or Immediate.
-            // Param 3 provides the reference to write to, which could be relative!
-            let mut modes = vec![param1, param2];
-            match param3 {
-                ParamMode::Relative => modes.push(ParamMode::RelativeReference),
-                ParamMode::Position => modes.push(ParamMode::Reference),
-                _ => unreachable!()
-            }
-            modes
-        }
-        Opcode::StoreInput => {
-            // The single param here is the destination of the input.
-            match param1 {
-                ParamMode::Relative => vec![ParamMode::RelativeReference],
-                ParamMode::Position => vec![ParamMode::Reference],
-                _ => unreachable!()
-            }
-        }
-        Opcode::PushOutput | Opcode::UpdateBase => {
-            // These have a single parameter which could be immediate or position.
-            vec![param1]
-        }
-        Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
-            // Both parameters can be Position or Immediate
-            vec![param1, param2]
-        }
-        Opcode::Halt => {
-            // This has no parameters.
-            Vec::new()
-        }
+    // Turns a mode into the one for a parameter that gets written to.
+    let reference = |mode| match mode {
+        ParamMode::Relative => Ok(ParamMode::RelativeReference),
+        ParamMode::Position => Ok(ParamMode::Reference),
+        _ => Err(IntcodeError::WriteInImmediateMode { pc, opcode: input }),
     };
+
+    // Parameters are read with their own modes, except the one written to
+    // (which could be relative!). Unused slots are left as Immediate.
+    let mut param_modes = [ParamMode::Immediate; MAX_PARAMS];
+    for (ii, &mode) in [param1, param2, param3].iter().enumerate().take(instruction.num_params) {
+        if digits[ii] > level.max_mode() {
+            return Err(IntcodeError::UnsupportedParamMode { pc, opcode: input, mode: digits[ii], level });
+        }
+        param_modes[ii] = if instruction.writes == Some(ii) { reference(mode)? } else { mode };
+    }
     debug!("param_modes: {:?}", param_modes);
 
-    (opcode, param_modes)
+    Ok((instruction, param_modes))
 }
 
 impl Operation {
     /// Builds a new Operation from the provided program, starting at the point
     /// indicated by the program counter (pc).
-    pub fn from(program: &Intcode, pc: i64) -> Self {
+    pub fn from<I, O, M: Memory>(program: &Intcode<I, O, M>, pc: i64) -> Result<Self, IntcodeError> {
         debug!("New Operation from position {}", pc);
-        let (opcode, param_modes) = parse_opcode(program.mem_get(pc));
-
-        // Work out how many parameters we need.
-        let num_params = match opcode {
-            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => 3,
-            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
-            Opcode::StoreInput | Opcode::PushOutput | Opcode::UpdateBase => 1,
-            Opcode::Halt => 0,
-        };
-        debug!("  no. params: {}", num_params);
+        Self::decode_with(program.mem_get(pc)?, pc, &program.isa)
+    }
+
+    /// Builds a new Operation from a single instruction cell, in the standard
+    /// instruction set. The pc is only used for error reporting.
+    pub fn decode(instruction: i64, pc: i64) -> Result<Self, IntcodeError> {
+        Self::decode_with(instruction, pc, isa::standard_set())
+    }
+
+    /// Builds a new Operation from a single instruction cell, in any
+    /// instruction set.
+    pub fn decode_with(instruction: i64, pc: i64, isa: &InstructionSet) -> Result<Self, IntcodeError> {
+        let (instruction, param_modes) = parse_opcode(instruction, pc, isa)?;
+        debug!("  no. params: {}", instruction.num_params);
+
+        Ok(Self {
+            code: instruction.code,
+            execute: instruction.execute,
+            opcode: instruction.opcode,
+            num_params: instruction.num_params as i64,
+            modes: param_modes,
+        })
+    }
 
-        Self {
-            opcode,
-            num_params,
-            param_modes,
+    /// Decodes the instruction at an address in a program image, along with
+    /// its parameters, if there's a complete one there.
+    pub fn decode_in<'a>(program: &'a [i64], address: usize, isa: &InstructionSet) -> Option<(Self, &'a [i64])> {
+        let op = Self::decode_with(*program.get(address)?, address as i64, isa).ok()?;
+        let params = program.get(address + 1..address + 1 + op.num_params as usize)?;
+        Some((op, params))
+    }
+
+    /// Whether every parameter this Operation reads is an immediate, so what
+    /// it does is known without running it.
+    fn reads_immediates(&self, skip: Option<usize>) -> bool {
+        self.param_modes()
+            .iter()
+            .enumerate()
+            .all(|(ii, mode)| Some(ii) == skip || !matches!(mode, ParamMode::Position | ParamMode::Relative))
+    }
+
+    /// The value this Operation stores, if it only reads immediates, so the
+    /// value is known without running it.
+    pub fn stored_constant(&self, params: &[i64]) -> Option<i64> {
+        match (self.execute)(params) {
+            Some(Effect::Store(value)) if self.reads_immediates(None) => Some(value),
+            _ => None,
         }
     }
 
+    /// Whether this Operation always halts.
+    pub fn halts(&self, params: &[i64]) -> bool {
+        self.reads_immediates(None) && (self.execute)(params) == Some(Effect::Halt)
+    }
+
+    /// Where this Operation jumps to, if `isa` says it's a jump. Whether it's
+    /// taken is known if all its other parameters are immediates.
+    pub fn jump(&self, params: &[i64], isa: &InstructionSet) -> Option<StaticJump> {
+        let target = isa.get(self.code)?.jumps?;
+        let taken = if self.reads_immediates(Some(target)) {
+            Some(matches!((self.execute)(params), Some(Effect::Jump(_))))
+        } else {
+            None
+        };
+        Some(StaticJump {
+            mode: self.param_modes()[target],
+            target: params[target],
+            taken,
+        })
+    }
+
+    /// Builds a new Operation the way the VM did before modes were kept in a
+    /// fixed-size array, by collecting them into a Vec.
+    fn decode_allocating(instruction: i64, pc: i64, isa: &InstructionSet) -> Result<Self, IntcodeError> {
+        let (instruction, param_modes) = parse_opcode(instruction, pc, isa)?;
+        let param_modes: Vec<ParamMode> = param_modes[..instruction.num_params].to_vec();
+        let mut modes = [ParamMode::Immediate; MAX_PARAMS];
+        modes[..param_modes.len()].copy_from_slice(&param_modes);
+
+        Ok(Self {
+            code: instruction.code,
+            execute: instruction.execute,
+            opcode: instruction.opcode,
+            num_params: instruction.num_params as i64,
+            modes,
+        })
+    }
+
+    /// The mnemonic for this Operation, given the instruction set it was
+    /// decoded with.
+    pub fn mnemonic(&self, isa: &InstructionSet) -> &'static str {
+        isa.get(self.code).map_or("?", |instruction| instruction.mnemonic)
+    }
+
+    /// The modes of the parameters this Operation actually takes.
+    pub fn param_modes(&self) -> &[ParamMode] {
+        &self.modes[..self.num_params as usize]
+    }
+
     /// Given a whole program, and the position of this Operation within it,
     /// works out what the parameters are for this Operation.
-    pub fn get_params(&self, program: &Intcode, pc: i64, base: i64) -> Vec<i64> {
-        let mut params: Vec<i64> = Vec::new();
+    pub fn get_params<I, O, M: Memory>(
+        &self,
+        program: &Intcode<I, O, M>,
+        pc: i64,
+        base: i64,
+    ) -> Result<[i64; MAX_PARAMS], IntcodeError> {
+        let mut params = [0; MAX_PARAMS];
 
-        for ii in 0..self.num_params as usize {
-            match self.param_modes[ii] {
+        for (ii, mode) in self.param_modes().iter().enumerate() {
+            let param = program.mem_get(offset_address(pc, ii as i64 + 1, pc)?)?;
+            params[ii] = match mode {
                 ParamMode::Position => {
                     // This is the number at the position indicated.
-                    let index = program.mem_get(pc + ii as i64 + 1);
-                    params.push(program.mem_get(index));
+                    program.mem_get(param)?
                 }
                 ParamMode::Immediate | ParamMode::Reference => {
                     // This is just the literal number in the parameter.
-                    params.push(program.mem_get(pc + ii as i64 + 1));
+                    param
                 }
                 ParamMode::Relative => {
                     // This is the number at the position indicated by 
                     // the current relative base, plus this parameter. 
-                    let param = program.mem_get(pc + ii as i64 + 1);
                     debug!("relative: {} + {}", param, base);
-                    params.push(program.mem_get(param + base));
-                }
-                ParamMode::RelativeReference => {
-                    params.push(program.mem_get(pc + ii as i64 + 1) + base);
+                    program.mem_get(offset_address(param, base, pc)?)?
                 }
-            }
+                ParamMode::RelativeReference => offset_address(param, base, pc)?,
+            };
         }
 
         debug!("got params: {:?}", params);
 
-        params
+        Ok(params)
+    }
+
+    /// Works out which memory addresses this Operation reads its parameters
+    /// from. Immediate parameters and write targets aren't reads.
+    pub fn read_addresses<I, O, M: Memory>(
+        &self,
+        program: &Intcode<I, O, M>,
+        pc: i64,
+        base: i64,
+    ) -> Result<Vec<i64>, IntcodeError> {
+        let mut addresses = Vec::new();
+
+        for (ii, mode) in self.param_modes().iter().enumerate() {
+            let param = || program.mem_get(offset_address(pc, ii as i64 + 1, pc)?);
+            match mode {
+                ParamMode::Position => addresses.push(param()?),
+                ParamMode::Relative => addresses.push(offset_address(param()?, base, pc)?),
+                _ => {}
+            }
+        }
+
+        Ok(addresses)
+    }
+
+    /// The address this Operation writes to, if any, given its parameters.
+    pub fn write_address(&self, params: &[i64]) -> Option<i64> {
+        self.param_modes()
+            .iter()
+            .position(|mode| matches!(mode, ParamMode::Reference | ParamMode::RelativeReference))
+            .map(|ii| params[ii])
     }
 }
 
 // Struct to store an intcode program.
+//
+// By default input is queued up in a VecDeque and output collected in a Vec,
+// but any InputSource / OutputSink can be plugged in with `with_io`. Likewise
+// memory is paged by default, but can be swapped with `with_memory`.
 #[derive(Debug, Clone)]
-pub struct Intcode {
-    pub program: Vec<i64>,
-    pub input: Vec<i64>,
-    pub output: Vec<i64>,
+pub struct Intcode<I = VecDeque<i64>, O = Vec<i64>, M = PagedMemory> {
+    pub program: M,
+    pub input: I,
+    pub output: O,
     pc: i64,
     relative_base: i64,
+    watchpoints: Vec<Watchpoint>,
+    trace: Option<Vec<TraceEvent>>,
+    history: Option<Vec<HistoryEntry>>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    isa: Arc<InstructionSet>,
+    // Instructions already decoded, by address, along with the cell they were
+    // decoded from. Shared between clones until one of them needs to change it.
+    decoded: Arc<Vec<Option<(i64, Operation)>>>,
+    decoder: Decoder,
 }
 
 impl Intcode {
-    pub fn from(input: &str) -> Self {
-        Self {
-            program: input
-                .trim()
-                .split(',')
-                .map(|s| s.parse().expect(&format!("Invalid entry: {}", s)))
-                .collect(),
-            input: Vec::new(),
+    pub fn from(input: &str) -> Result<Self, IntcodeError> {
+        let program = input
+            .trim()
+            .split(',')
+            .enumerate()
+            .map(|(position, s)| {
+                s.trim().parse().map_err(|_| IntcodeError::ParseError {
+                    position,
+                    entry: s.to_string(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self::new(program))
+    }
+
+    /// Builds a machine around an already-parsed program.
+    pub fn new(program: Vec<i64>) -> Self {
+        let mut machine = Self {
+            program: program.into(),
+            input: VecDeque::new(),
             output: Vec::new(),
             pc: 0,
             relative_base: 0,
-        }
+            watchpoints: Vec::new(),
+            trace: None,
+            history: None,
+            profile: None,
+            coverage: None,
+            isa: isa::standard_set().clone(),
+            decoded: Arc::new(Vec::new()),
+            decoder: Decoder::Cached,
+        };
+        machine.fill_decode_cache();
+        machine
     }
 
-    pub fn from_file(name: &str) -> Self {
-        let data = std::fs::read_to_string(name).expect("Unable to read file");
+    pub fn from_file(name: &str) -> Result<Self, IntcodeError> {
+        let data = read_file(name)?;
 
         Self::from(&data)
     }
+}
+
+impl<I, O, M: Memory> Intcode<I, O, M> {
+    /// Swaps out this machine's input and output, keeping everything else.
+    pub fn with_io<I2, O2>(self, input: I2, output: O2) -> Intcode<I2, O2, M> {
+        Intcode {
+            program: self.program,
+            input,
+            output,
+            pc: self.pc,
+            relative_base: self.relative_base,
+            watchpoints: self.watchpoints,
+            trace: self.trace,
+            history: self.history,
+            profile: self.profile,
+            coverage: self.coverage,
+            isa: self.isa,
+            decoded: self.decoded,
+            decoder: self.decoder,
+        }
+    }
+
+    /// Swaps out this machine's memory, keeping everything else.
+    pub fn with_memory<M2: Memory>(self, program: M2) -> Intcode<I, O, M2> {
+        Intcode {
+            program,
+            input: self.input,
+            output: self.output,
+            pc: self.pc,
+            relative_base: self.relative_base,
+            watchpoints: self.watchpoints,
+            trace: self.trace,
+            history: self.history,
+            profile: self.profile,
+            coverage: self.coverage,
+            isa: self.isa,
+            decoded: self.decoded,
+            decoder: self.decoder,
+        }
+    }
+
+    /// The current program counter.
+    pub fn pc(&self) -> i64 {
+        self.pc
+    }
+
+    /// The current relative base.
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    /// Safely retrieves the data at a given memory address. Memory beyond the
+    /// end of the program reads as zero.
+    pub fn mem_get(&self, address: i64) -> Result<i64, IntcodeError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress { pc: self.pc, address });
+        }
+        Ok(self.program.load(address as usize))
+    }
+
+    // Stores a value at a memory location.
+    fn mem_set(&mut self, address: i64, value: i64) -> Result<(), IntcodeError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress { pc: self.pc, address });
+        }
+        self.program.store(address as usize, value);
+        Ok(())
+    }
+
+    /// Decodes every cell of the image that holds a valid instruction, so
+    /// that clones made from here on share the table rather than each
+    /// filling in their own.
+    fn fill_decode_cache(&mut self) {
+        let mut decoded = Vec::new();
+        if self.decoder == Decoder::Cached {
+            let size = self.program.extent().min(DECODE_CACHE_LIMIT);
+            decoded = (0..size)
+                .map(|address| {
+                    let cell = self.program.load(address);
+                    Operation::decode_with(cell, address as i64, &self.isa).ok().map(|op| (cell, op))
+                })
+                .collect();
+        }
+        self.decoded = Arc::new(decoded);
+    }
+
+    /// Decodes the instruction at an address, using the cache if it's there.
+    /// Each hit is checked against the current cell, so writes into code
+    /// (whether through `mem_set` or straight to `program`) never need to
+    /// touch the table.
+    fn decode_at(&mut self, address: i64) -> Result<Operation, IntcodeError> {
+        let instruction = self.mem_get(address)?;
+        let index = address as usize;
+        if let Some(Some((cell, op))) = self.decoded.get(index) {
+            if *cell == instruction {
+                return Ok(*op);
+            }
+        }
+
+        let op = match self.decoder {
+            Decoder::Allocating => Operation::decode_allocating(instruction, address, &self.isa)?,
+            _ => Operation::decode_with(instruction, address, &self.isa)?,
+        };
+        if self.decoder == Decoder::Cached && index < DECODE_CACHE_LIMIT {
+            // Self-modifying code: this copies the table first if it's
+            // shared, so only machines that patch their code pay for it.
+            let decoded = Arc::make_mut(&mut self.decoded);
+            if index >= decoded.len() {
+                let size = self.program.extent().clamp(index + 1, DECODE_CACHE_LIMIT);
+                decoded.resize(size, None);
+            }
+            decoded[index] = Some((instruction, op));
+        }
+        Ok(op)
+    }
+
+    /// Chooses how instructions are decoded. The cache is used by default;
+    /// the others are mostly useful for benchmarking.
+    pub fn set_decoder(&mut self, decoder: Decoder) {
+        self.decoder = decoder;
+        self.fill_decode_cache();
+    }
+
+    /// Switches to another instruction set, such as a dialect with extra
+    /// instructions. Machines use the standard one unless told otherwise.
+    pub fn set_instruction_set(&mut self, isa: InstructionSet) {
+        self.isa = Arc::new(isa);
+        self.fill_decode_cache();
+    }
+
+    pub fn instruction_set(&self) -> &InstructionSet {
+        &self.isa
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes all watchpoints on an address, returning whether there were
+    /// any.
+    pub fn remove_watchpoints(&mut self, address: i64) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.address != address);
+        self.watchpoints.len() != before
+    }
 
-    /// Safely retrieves the data at a given memory address.
-    pub fn mem_get(&self, address: i64) -> i64 {
-        *self.program.get(address as usize).unwrap_or(&0)
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
     }
 
-    // Stores a value at a memory location, enlarging the memory if needed.
-    fn mem_set(&mut self, address: i64, value: i64) {
-        let address = address as usize;
-        if address >= self.program.len() {
-            self.program.resize(address + 1, 0);
+    /// Starts recording a trace of each instruction executed, throwing away
+    /// any trace recorded so far.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// Stops tracing, and returns the trace recorded.
+    pub fn take_trace(&mut self) -> Vec<TraceEvent> {
+        self.trace.take().unwrap_or_default()
+    }
+
+    /// Finds the first watchpoint, if any, triggered by an access.
+    fn check_watchpoints(&self, address: i64, access: Access, value: i64) -> Option<WatchHit> {
+        if self.watchpoints.iter().any(|w| w.matches(address, access, value)) {
+            Some(WatchHit {
+                pc: self.pc,
+                address,
+                access,
+                value,
+            })
+        } else {
+            None
         }
-        self.program[address] = value.into();
     }
+}
 
+impl<I: InputSource, O: OutputSink, M: Memory> Intcode<I, O, M> {
     /// Perform a single operation, starting at the program counter (pc).
     ///
-    /// Returns a StepResult (Halt or Continue).
-    fn step(&mut self) -> StepResult {
-        // let program = &mut self.program;
-
+    /// Returns a StepResult (Halt, Continue, Output or NeedInput). On error,
+    /// the pc is left pointing at the faulting instruction.
+    ///
+    /// If the instruction triggered a watchpoint, it still completes (so any
+    /// output is still pushed) but Watchpoint is returned instead.
+    pub fn step(&mut self) -> Result<StepResult, IntcodeError> {
         // Calculate what the next operation is.
-        let op = Operation::from(&self, self.pc);
+        let op = self.decode_at(self.pc)?;
 
         // Get the parameters. This deals with parameter modes so that the
         // value in this vector is the one we need below.
-        let params = op.get_params(&self, self.pc, self.relative_base);
+        let params = op.get_params(self, self.pc, self.relative_base)?;
+
+        // If we're tracing, keep hold of the instruction in case it gets
+        // overwritten.
+        let start_pc = self.pc;
+        let instruction = if self.trace.is_some() { self.mem_get(self.pc)? } else { 0 };
+        let mut input_taken = None;
+
+        // Likewise, if we're keeping history, we need the value about to be
+        // overwritten so this instruction can be undone.
+        let start_base = self.relative_base;
+        let overwritten = match (&self.history, op.write_address(&params)) {
+            (Some(_), Some(address)) => Some((address, self.mem_get(address)?)),
+            _ => None,
+        };
+
+        // Check for watched reads before anything changes.
+        let mut hit = None;
+        if !self.watchpoints.is_empty() {
+            for address in op.read_addresses(self, self.pc, self.relative_base)? {
+                let value = self.mem_get(address)?;
+                hit = hit.or_else(|| self.check_watchpoints(address, Access::Read, value));
+            }
+        }
+
+        // If we're profiling or measuring coverage, we need the addresses
+        // this instruction reads from as well.
+        let reads = if self.profile.is_some() || self.coverage.is_some() {
+            op.read_addresses(self, self.pc, self.relative_base)?
+        } else {
+            Vec::new()
+        };
 
         let mut result = StepResult::Continue;
         let mut pc_moved = false;
 
-        // Perform the operation.
-        match op.opcode {
-            Opcode::Add => {
-                // Add the first to the second, store in the third.
-                debug!("{} + {} -> [{}]", params[0], params[1], params[2]);
-                self.mem_set(params[2], params[0] + params[1]);
-            }
-            Opcode::Multiply => {
-                // Multiply the first and the second, store in the third.
-                debug!("{} * {} -> [{}]", params[0], params[1], params[2]);
-                self.mem_set(params[2], params[0] * params[1]);
+        // Work out what the instruction does, then do it.
+        let effect = (op.execute)(&params[..op.num_params as usize])
+            .ok_or(IntcodeError::Overflow { pc: self.pc })?;
+        debug!("{} {:?}: {:?}", op.mnemonic(&self.isa), params, effect);
+        let write_address = || {
+            op.write_address(&params).ok_or(IntcodeError::NoWriteParameter { pc: start_pc, opcode: op.code })
+        };
+        match effect {
+            Effect::Nothing => {}
+            Effect::Store(value) => {
+                let address = write_address()?;
+                self.mem_set(address, value)?;
             }
-            Opcode::StoreInput => {
+            Effect::Input => {
                 // Get the first value off the input stack; store it in the
-                // cell indicated by the first parameter.
-                let input = self.input.remove(0);
-                debug!("{} -> [{}]", input, params[0]);
-                self.mem_set(params[0], input);
+                // cell indicated by the write parameter. If there isn't any
+                // input yet, leave the pc here so we can resume later.
+                let address = write_address()?;
+                let input = match self.input.next_input() {
+                    Some(input) => input,
+                    None => {
+                        debug!("Waiting for input");
+                        return Ok(StepResult::NeedInput);
+                    }
+                };
+                self.mem_set(address, input)?;
+                input_taken = Some(input);
             }
-            Opcode::PushOutput => {
-                // Push the first parameter to the output stack.
-                debug!("{} -> output", params[0]);
-                self.output.push(params[0]);
+            Effect::Output(value) => {
+                self.output.push_output(value);
                 result = StepResult::Output;
             }
-            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
-                // If the condition is met by the first param, move the
-                // instruction pointer to the second param.
-                debug!("{:?} : {}?", op.opcode, params[0]);
-
-                let condition: bool;
-
-                // TODO: would be nice to do this without an if statement
-                if op.opcode == Opcode::JumpIfTrue {
-                    condition = params[0] != 0;
-                } else {
-                    // JumpIfFalse
-                    condition = params[0] == 0;
-                }
-
-                if condition {
-                    debug!("Set PC to {}", params[1]);
-                    self.pc = params[1];
-                    pc_moved = true;
-                }
-            }
-            Opcode::LessThan | Opcode::Equals => {
-                // If the first param is less than / equal to the second,
-                // store '1' in the position given by the third param.
-                // Otherwise, store '0'.
-                debug!("{:?} : {} ? {}", op.opcode, params[0], params[1]);
-
-                let condition: bool;
-
-                if op.opcode == Opcode::LessThan {
-                    condition = params[0] < params[1];
-                } else {
-                    // Equals
-                    condition = params[0] == params[1];
-                }
-
-                if condition {
-                    debug!("Store 1 in slot {}", params[2]);
-                    self.mem_set(params[2], 1);
-                } else {
-                    debug!("Store 0 in slot {}", params[2]);
-                    self.mem_set(params[2], 0);
-                }
+            Effect::Jump(target) => {
+                debug!("Set PC to {}", target);
+                self.pc = target;
+                pc_moved = true;
             }
-            Opcode::UpdateBase => {
-                debug!("updatebase: {}", params[0]);
-                self.relative_base += params[0];
+            Effect::AdjustBase(adjustment) => {
+                self.relative_base = self
+                    .relative_base
+                    .checked_add(adjustment)
+                    .ok_or(IntcodeError::Overflow { pc: self.pc })?;
                 debug!("  base is now {}", self.relative_base);
             }
-            Opcode::Halt => {
+            Effect::Halt => {
                 debug!("Halt!");
                 result = StepResult::Halt;
             }
         };
 
+        // Check for a watched write, now we know what was written.
+        if let (false, Some(address)) = (self.watchpoints.is_empty(), op.write_address(&params)) {
+            let value = self.mem_get(address)?;
+            hit = hit.or_else(|| self.check_watchpoints(address, Access::Write, value));
+        }
+
+        if let Some(history) = self.history.as_mut() {
+            history.push(HistoryEntry {
+                pc: start_pc,
+                relative_base: start_base,
+                overwritten,
+                input: input_taken,
+                output: matches!(effect, Effect::Output(_)),
+            });
+        }
+
+        if let Some(profile) = self.profile.as_mut() {
+            let touched = reads
+                .iter()
+                .copied()
+                .chain(op.write_address(&params))
+                .fold(start_pc + op.num_params, i64::max);
+            let output = matches!(effect, Effect::Output(_));
+            profile.record(start_pc, op.mnemonic(&self.isa), output, touched, self.relative_base);
+        }
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(start_pc, op.num_params, &reads, op.write_address(&params));
+        }
+
+        if self.trace.is_some() {
+            let write = match op.write_address(&params) {
+                Some(address) => Some((address, self.mem_get(address)?)),
+                None => None,
+            };
+            let event = TraceEvent {
+                pc: start_pc,
+                instruction,
+                write,
+                input: input_taken,
+                output: if let Effect::Output(value) = effect { Some(value) } else { None },
+                relative_base: if let Effect::AdjustBase(_) = effect {
+                    Some(self.relative_base)
+                } else {
+                    None
+                },
+                params: params[..op.num_params as usize].to_vec(),
+            };
+            if let Some(trace) = self.trace.as_mut() {
+                trace.push(event);
+            }
+        }
+        if let Some(hit) = hit {
+            debug!("Watchpoint: {:?}", hit);
+            result = StepResult::Watchpoint(hit);
+        }
+
         // Advance the program counter, if it hasn't already changed.
         if !pc_moved {
             debug!("Advancing PC");
-            self.pc += op.num_params + 1;
+            self.pc = offset_address(self.pc, op.num_params + 1, self.pc)?;
         }
         debug!("PC is now {}", self.pc);
 
         debug!("Returning {:?}", result);
-        result
+        Ok(result)
     }
 
-    /// Runs step-by-step until it encounters a Halt.
-    pub fn run(&mut self) {
+    /// Runs step-by-step until it encounters a Halt. Running out of input is
+    /// an error here.
+    pub fn run(&mut self) -> Result<(), IntcodeError> {
         let mut result = StepResult::Continue;
 
         while result != StepResult::Halt {
-            result = self.step();
+            result = self.step()?;
+            if result == StepResult::NeedInput {
+                return Err(IntcodeError::InputExhausted { pc: self.pc });
+            }
         }
+
+        Ok(())
     }
 
-    /// Runs step-by-step until something is pushed to output, or the program halts.
-    pub fn run_until_output(&mut self) -> StepResult {
+    /// Runs step-by-step until the program wants input that isn't there yet,
+    /// or halts. Output is left in `output` for the caller to collect.
+    ///
+    /// On NeedInput the pc still points at the StoreInput instruction, so the
+    /// caller can push more input and call this again to resume.
+    pub fn run_until_input_or_halt(&mut self) -> Result<StepResult, IntcodeError> {
+        loop {
+            match self.step()? {
+                StepResult::Continue | StepResult::Output => {}
+                result => return Ok(result),
+            }
+        }
+    }
+
+    /// Runs step-by-step until something is pushed to output, the program
+    /// halts, or it needs more input.
+    pub fn run_until_output(&mut self) -> Result<StepResult, IntcodeError> {
         let mut result = StepResult::Continue;
 
         while result == StepResult::Continue {
-            result = self.step();
+            result = self.step()?;
         }
 
-        result
+        Ok(result)
     }
 }
 
@@ -355,41 +913,215 @@ mod tests {
     #[test]
     fn simple_test_of_io_opcodes() {
         init();
-        let mut program = Intcode::from("3,0,4,0,99");
-        program.input.push(256);
-        program.run();
+        let mut program = Intcode::from("3,0,4,0,99").unwrap();
+        program.input.push_back(256);
+        program.run().unwrap();
         assert_eq!(vec![256], program.output);
     }
 
     #[test]
     fn test_jump_if_true_1() {
         init();
-        let mut program = Intcode::from("1105,2,77,99");
-        program.step();
+        let mut program = Intcode::from("1105,2,77,99").unwrap();
+        program.step().unwrap();
         assert_eq!(77, program.pc);
     }
 
     #[test]
     fn test_jump_if_true_2() {
         init();
-        let mut program = Intcode::from("1105,0,77,99");
-        program.step();
+        let mut program = Intcode::from("1105,0,77,99").unwrap();
+        program.step().unwrap();
         assert_eq!(3, program.pc);
     }
 
     #[test]
     fn test_jump_if_false_1() {
         init();
-        let mut program = Intcode::from("1106,0,77,99");
-        program.step();
+        let mut program = Intcode::from("1106,0,77,99").unwrap();
+        program.step().unwrap();
         assert_eq!(77, program.pc);
     }
 
     #[test]
     fn test_jump_if_false_2() {
         init();
-        let mut program = Intcode::from("1106,2,77,99");
-        program.step();
+        let mut program = Intcode::from("1106,2,77,99").unwrap();
+        program.step().unwrap();
         assert_eq!(3, program.pc);
     }
+
+    #[test]
+    fn self_modifying_code() {
+        init();
+        // Outputs 5, overwrites that instruction with a halt and jumps back.
+        let mut program = Intcode::from("104,5,1101,0,99,0,1105,1,0").unwrap();
+        program.run().unwrap();
+        assert_eq!(vec![5], program.output);
+    }
+
+    #[test]
+    fn clones_share_decoded_instructions() {
+        init();
+        let base = Intcode::from("104,5,1101,0,99,0,1105,1,0").unwrap();
+        let mut patched = base.clone();
+        let mut unpatched = base.clone();
+        assert!(Arc::ptr_eq(&base.decoded, &patched.decoded));
+
+        // Patching its own code gives a machine its own table, and leaves the
+        // others alone.
+        patched.run().unwrap();
+        assert!(!Arc::ptr_eq(&base.decoded, &patched.decoded));
+        unpatched.step().unwrap();
+        assert!(Arc::ptr_eq(&base.decoded, &unpatched.decoded));
+
+        for decoder in [Decoder::Allocating, Decoder::Fresh] {
+            let mut program = base.clone();
+            program.set_decoder(decoder);
+            program.run().unwrap();
+            assert_eq!(vec![5], program.output);
+        }
+    }
+
+    #[test]
+    fn patching_memory_directly() {
+        init();
+        let mut program = Intcode::from("104,5,99").unwrap();
+        program.run().unwrap();
+
+        // Rerun with the output instruction patched out.
+        program.program[0] = 99;
+        program.pc = 0;
+        program.run().unwrap();
+        assert_eq!(vec![5], program.output);
+    }
+
+    #[test]
+    fn waits_for_input() {
+        init();
+        let mut program = Intcode::from("3,0,4,0,99").unwrap();
+        assert_eq!(StepResult::NeedInput, program.run_until_input_or_halt().unwrap());
+        assert_eq!(0, program.pc);
+
+        program.input.push_back(7);
+        assert_eq!(StepResult::Halt, program.run_until_input_or_halt().unwrap());
+        assert_eq!(vec![7], program.output);
+    }
+
+    #[test]
+    fn watchpoints() {
+        init();
+        let mut program = Intcode::from("1,9,10,3,2,3,11,0,99,30,40,50").unwrap();
+        program.add_watchpoint(Watchpoint::read(10));
+        program.add_watchpoint(Watchpoint::write(0).when(|value| value > 1000));
+
+        let read = WatchHit { pc: 0, address: 10, access: Access::Read, value: 40 };
+        assert_eq!(StepResult::Watchpoint(read), program.step().unwrap());
+
+        let write = WatchHit { pc: 4, address: 0, access: Access::Write, value: 3500 };
+        assert_eq!(StepResult::Watchpoint(write), program.run_until_output().unwrap());
+
+        assert!(program.remove_watchpoints(0));
+        assert_eq!(StepResult::Halt, program.run_until_output().unwrap());
+    }
+
+    #[test]
+    fn huge_relative_write() {
+        init();
+        let mut program = Intcode::from("109,1000000000000,21101,3,4,0,204,0,99").unwrap();
+        program.run().unwrap();
+        assert_eq!(vec![7], program.output);
+    }
+
+    #[test]
+    fn dense_memory() {
+        init();
+        let program = Intcode::from("1,0,0,7,99").unwrap();
+        let memory = program.program.to_vec();
+        let mut program = program.with_memory(memory);
+        program.run().unwrap();
+        assert_eq!(vec![1, 0, 0, 7, 99, 0, 0, 2], program.program);
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        init();
+        let mut program = Intcode::from("1102,9223372036854775807,2,0,99").unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: 0 }), program.run());
+    }
+
+    #[test]
+    fn relative_address_overflow_is_an_error() {
+        init();
+        let mut program = Intcode::from("109,1,201,9223372036854775807,0,0,99").unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: 2 }), program.run());
+        let mut program = Intcode::from("109,1,21101,0,0,9223372036854775807,99").unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: 2 }), program.run());
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error() {
+        init();
+        let mut program = Intcode::from("1,0,0,0,42").unwrap();
+        assert_eq!(
+            Err(IntcodeError::UnknownOpcode { pc: 4, opcode: 42 }),
+            program.run()
+        );
+        assert_eq!(4, program.pc);
+    }
+
+    #[test]
+    fn invalid_param_mode_is_an_error() {
+        init();
+        let mut program = Intcode::from("304,0,99").unwrap();
+        assert_eq!(
+            Err(IntcodeError::InvalidParamMode { pc: 0, opcode: 304, mode: 3 }),
+            program.run()
+        );
+    }
+
+    #[test]
+    fn immediate_write_is_an_error() {
+        init();
+        let mut program = Intcode::from("10001,0,0,0,99").unwrap();
+        assert_eq!(
+            Err(IntcodeError::WriteInImmediateMode { pc: 0, opcode: 10001 }),
+            program.run()
+        );
+    }
+
+    #[test]
+    fn negative_address_is_an_error() {
+        init();
+        let mut program = Intcode::from("1,-1,0,0,99").unwrap();
+        assert_eq!(
+            Err(IntcodeError::NegativeAddress { pc: 0, address: -1 }),
+            program.run()
+        );
+    }
+
+    #[test]
+    fn missing_input_is_an_error() {
+        init();
+        let mut program = Intcode::from("3,0,99").unwrap();
+        assert_eq!(Err(IntcodeError::InputExhausted { pc: 0 }), program.run());
+    }
+
+    #[test]
+    fn bad_entry_is_a_parse_error() {
+        init();
+        assert_eq!(
+            IntcodeError::ParseError { position: 2, entry: "x".to_string() },
+            Intcode::from("1,0,x,0,99").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        init();
+        match Intcode::from_file("no-such-input.txt") {
+            Err(IntcodeError::Io { name, .. }) => assert_eq!("no-such-input.txt", name),
+            other => panic!("expected an I/O error, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,201 @@
+//! Talking to Intcode programs that speak ASCII: they print character codes,
+//! and read commands a character at a time, ending with a newline.
+
+use super::{Intcode, IntcodeError, StepResult};
+use std::io::{BufRead, Write};
+
+/// Something printed by a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsciiOutput {
+    /// A line of text, without its newline.
+    Line(String),
+    /// A value outside the ASCII range, passed through as it is.
+    Value(i64),
+}
+
+/// Converts text to input values, one per byte.
+pub fn encode(text: &str) -> Vec<i64> {
+    text.bytes().map(i64::from).collect()
+}
+
+/// Wraps a machine to send it text and collect its output as lines.
+#[derive(Debug, Clone)]
+pub struct Ascii {
+    pub machine: Intcode,
+    /// Text printed since the last newline.
+    partial: String,
+    output: Vec<AsciiOutput>,
+}
+
+impl Ascii {
+    pub fn new(machine: Intcode) -> Self {
+        Self {
+            machine,
+            partial: String::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Queues up text as input, as it is.
+    pub fn send(&mut self, text: &str) {
+        self.machine.input.extend(encode(text));
+    }
+
+    /// Queues up a command, adding the newline that ends it.
+    pub fn send_line(&mut self, command: &str) {
+        self.send(command);
+        self.machine.input.push_back(i64::from(b'\n'));
+    }
+
+    /// Runs until the program wants input that isn't there yet, or halts,
+    /// collecting what it prints.
+    pub fn run(&mut self) -> Result<StepResult, IntcodeError> {
+        let result = self.machine.run_until_input_or_halt()?;
+        self.collect();
+        Ok(result)
+    }
+
+    /// Sorts the machine's output into lines and values. A value outside the
+    /// ASCII range ends any partial line first, so the order is kept.
+    fn collect(&mut self) {
+        for value in self.machine.output.drain(..) {
+            match value {
+                10 => self.output.push(AsciiOutput::Line(std::mem::take(&mut self.partial))),
+                0..=127 => self.partial.push(value as u8 as char),
+                _ => {
+                    if !self.partial.is_empty() {
+                        self.output.push(AsciiOutput::Line(std::mem::take(&mut self.partial)));
+                    }
+                    self.output.push(AsciiOutput::Value(value));
+                }
+            }
+        }
+    }
+
+    /// Takes the lines and values printed so far. Text after the last newline
+    /// (often a prompt) is left in `partial`.
+    pub fn take_output(&mut self) -> Vec<AsciiOutput> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Text printed since the last newline.
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    /// Runs the program, printing what it prints to `output` and answering
+    /// each request for input with the next line of `input`. With `echo`, the
+    /// commands are printed too, which makes a readable transcript when they
+    /// come from a file.
+    ///
+    /// Returns Halt when the program halts, or NeedInput if `input` ran out
+    /// first.
+    pub fn interact<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+        echo: bool,
+    ) -> Result<StepResult, IntcodeError> {
+        let mut commands = input.lines();
+
+        loop {
+            let result = self.run()?;
+            for item in self.take_output() {
+                match item {
+                    AsciiOutput::Line(line) => writeln!(output, "{}", line),
+                    AsciiOutput::Value(value) => writeln!(output, "{}", value),
+                }
+                .expect("Unable to write output");
+            }
+            let prompt = std::mem::take(&mut self.partial);
+            write!(output, "{}", prompt).expect("Unable to write output");
+            output.flush().expect("Unable to write output");
+
+            if result != StepResult::NeedInput {
+                return Ok(result);
+            }
+            let command = match commands.next() {
+                Some(Ok(command)) => command,
+                _ => return Ok(result),
+            };
+            let command = command.trim_end_matches('\r');
+            if echo {
+                writeln!(output, "{}", command).expect("Unable to write output");
+            }
+            self.send_line(command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // Prompts with "> " and echoes each command, until one starts with 'q'.
+    // Then prints 1000 and halts.
+    const ECHO: &str = "
+        prompt: out #62
+                out #32
+                in -> [char]
+                eq [char], #113 -> [test]
+                jt [test], #quit
+        line:   out [char]
+                eq [char], #10 -> [test]
+                jt [test], #prompt
+                in -> [char]
+                jt #1, #line
+        quit:   out #1000
+                hlt
+        char:   db 0
+        test:   db 0
+    ";
+
+    fn echo() -> Ascii {
+        Ascii::new(Intcode::from(&assemble(ECHO).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn lines_and_values() {
+        init();
+        let mut ascii = echo();
+        assert_eq!(Ok(StepResult::NeedInput), ascii.run());
+        assert_eq!(Vec::<AsciiOutput>::new(), ascii.take_output());
+        assert_eq!("> ", ascii.partial());
+
+        ascii.send_line("look");
+        ascii.send("q");
+        assert_eq!(Ok(StepResult::Halt), ascii.run());
+        assert_eq!(
+            vec![
+                AsciiOutput::Line("> look".to_string()),
+                AsciiOutput::Line("> ".to_string()),
+                AsciiOutput::Value(1000)
+            ],
+            ascii.take_output()
+        );
+        assert_eq!("", ascii.partial());
+    }
+
+    #[test]
+    fn scripted_session() {
+        init();
+        let mut transcript = Vec::new();
+        let result = echo().interact("look\r\nq\n".as_bytes(), &mut transcript, true);
+        assert_eq!(Ok(StepResult::Halt), result);
+        assert_eq!("> look\nlook\n> q\n1000\n", String::from_utf8(transcript).unwrap());
+    }
+
+    #[test]
+    fn script_runs_out() {
+        init();
+        let mut transcript = Vec::new();
+        let result = echo().interact("look\n".as_bytes(), &mut transcript, false);
+        assert_eq!(Ok(StepResult::NeedInput), result);
+        assert_eq!("> look\n> ", String::from_utf8(transcript).unwrap());
+    }
+}
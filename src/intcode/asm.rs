@@ -0,0 +1,358 @@
+//! A small assembler for Intcode.
+//!
+//! The syntax matches the disassembler's output, so listings can be fed back
+//! in. Each line holds an optional label, an instruction and an optional
+//! comment:
+//!
+//! ```text
+//! loop:   in -> [rb+1]        ; read a value
+//!         mul [rb+1], #2 -> [total]
+//!         out [total]
+//!         jt #1, #loop
+//! total:  db 0
+//! ```
+//!
+//! Parameters are `#value` (immediate), `[address]` (position) or
+//! `[rb+offset]` (relative). Values can be numbers or labels. The write target
+//! can either come after `->` or just be the last parameter. `db` (or `data`)
+//! emits its comma-separated values as they are. Numeric labels like `0004:`,
+//! as printed by the disassembler, are ignored.
+
+use super::isa::{standard_set, InstructionSet};
+use std::collections::HashMap;
+use std::fmt;
+
+/// An error in assembler source, with the (1-based) line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A value in the source, which might not be known until all the labels are.
+#[derive(Debug)]
+enum Value {
+    Number(i64),
+    Label { name: String, negate: bool },
+}
+
+fn parse_value(text: &str, line: usize) -> Result<Value, AsmError> {
+    let text = text.trim();
+    if let Ok(number) = text.parse() {
+        return Ok(Value::Number(number));
+    }
+    if is_label(text) {
+        return Ok(Value::Label {
+            name: text.to_string(),
+            negate: false,
+        });
+    }
+    Err(AsmError {
+        line,
+        message: format!("invalid value {:?}", text),
+    })
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Parses a single parameter into its mode digit and value.
+fn parse_param(text: &str, line: usize) -> Result<(i64, Value), AsmError> {
+    let text = text.trim();
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok((1, parse_value(value, line)?));
+    }
+    if text.starts_with('[') && text.ends_with(']') {
+        let inner = text[1..text.len() - 1].trim();
+        if inner == "rb" {
+            return Ok((2, Value::Number(0)));
+        }
+        if let Some(offset) = inner.strip_prefix("rb") {
+            let offset = offset.trim();
+            let (negate, value) = if let Some(value) = offset.strip_prefix('-') {
+                (true, value)
+            } else if let Some(value) = offset.strip_prefix('+') {
+                (false, value)
+            } else {
+                return Err(AsmError {
+                    line,
+                    message: format!("invalid relative parameter {:?}", text),
+                });
+            };
+            let value = match parse_value(value, line)? {
+                Value::Number(number) if negate => Value::Number(-number),
+                Value::Label { name, .. } => Value::Label { name, negate },
+                value => value,
+            };
+            return Ok((2, value));
+        }
+        return Ok((0, parse_value(inner, line)?));
+    }
+    Err(AsmError {
+        line,
+        message: format!("expected #value, [address] or [rb+offset], got {:?}", text),
+    })
+}
+
+/// Splits a comma-separated list, ignoring a trailing empty entry.
+fn split_list(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    if text.is_empty() {
+        Vec::new()
+    } else {
+        text.split(',').map(str::trim).collect()
+    }
+}
+
+/// Assembles one instruction into its cells.
+fn assemble_instruction(
+    isa: &InstructionSet,
+    mnemonic: &str,
+    operands: &str,
+    line: usize,
+) -> Result<Vec<Value>, AsmError> {
+    let lower = mnemonic.to_ascii_lowercase();
+    let instruction = isa.by_mnemonic(&lower).ok_or_else(|| AsmError {
+        line,
+        message: format!("unknown mnemonic {:?}", mnemonic),
+    })?;
+    let num_params = instruction.num_params;
+
+    // A write target after `->` goes wherever the instruction expects it,
+    // which is usually last.
+    let mut params = Vec::new();
+    match (operands.find("->"), instruction.writes) {
+        (Some(arrow), Some(writes)) => {
+            params.extend(split_list(&operands[..arrow]));
+            params.insert(writes.min(params.len()), operands[arrow + 2..].trim());
+        }
+        (Some(_), None) => {
+            return Err(AsmError {
+                line,
+                message: format!("{} doesn't write to anything", lower),
+            })
+        }
+        (None, _) => params.extend(split_list(operands)),
+    }
+    if params.len() != num_params {
+        return Err(AsmError {
+            line,
+            message: format!(
+                "{} takes {} parameters, got {}",
+                lower,
+                num_params,
+                params.len()
+            ),
+        });
+    }
+
+    let mut code = instruction.code;
+    let mut cells = vec![];
+    for (ii, param) in params.iter().enumerate() {
+        let (mode, value) = parse_param(param, line)?;
+        if instruction.writes == Some(ii) && mode == 1 {
+            return Err(AsmError {
+                line,
+                message: format!("{} can't write to an immediate parameter", lower),
+            });
+        }
+        code += mode * 10_i64.pow(ii as u32 + 2);
+        cells.push(value);
+    }
+    cells.insert(0, Value::Number(code));
+
+    Ok(cells)
+}
+
+/// Assembles source into a comma-separated program that `Intcode::from` can
+/// load.
+pub fn assemble(source: &str) -> Result<String, AsmError> {
+    standard_set().assemble(source)
+}
+
+impl InstructionSet {
+    /// Assembles source written for this instruction set, in the same way as
+    /// `assemble`.
+    pub fn assemble(&self, source: &str) -> Result<String, AsmError> {
+        let mut cells: Vec<(usize, Value)> = Vec::new();
+        let mut labels = HashMap::new();
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let mut text = text.split(';').next().unwrap().trim();
+
+            // Pull off any labels at the start of the line.
+            while let Some(colon) = text.find(':') {
+                let label = text[..colon].trim();
+                if label.parse::<i64>().is_ok() {
+                    // An address from a disassembly listing.
+                } else if is_label(label) {
+                    if labels.insert(label.to_string(), cells.len() as i64).is_some() {
+                        return Err(AsmError {
+                            line,
+                            message: format!("label {:?} defined twice", label),
+                        });
+                    }
+                } else {
+                    break;
+                }
+                text = text[colon + 1..].trim();
+            }
+
+            if text.is_empty() {
+                continue;
+            }
+
+            let (mnemonic, operands) = match text.find(char::is_whitespace) {
+                Some(space) => (&text[..space], &text[space..]),
+                None => (text, ""),
+            };
+
+            let values = match mnemonic.to_ascii_lowercase().as_str() {
+                "db" | "data" => split_list(operands)
+                    .iter()
+                    .map(|value| parse_value(value, line))
+                    .collect::<Result<_, _>>()?,
+                _ => assemble_instruction(self, mnemonic, operands, line)?,
+            };
+            cells.extend(values.into_iter().map(|value| (line, value)));
+        }
+
+        // Now we know where all the labels are, fill them in.
+        let program = cells
+            .into_iter()
+            .map(|(line, value)| match value {
+                Value::Number(number) => Ok(number),
+                Value::Label { name, negate } => match labels.get(&name) {
+                    Some(&address) if negate => Ok(-address),
+                    Some(&address) => Ok(address),
+                    None => Err(AsmError {
+                        line,
+                        message: format!("undefined label {:?}", name),
+                    }),
+                },
+            })
+            .collect::<Result<Vec<i64>, _>>()?;
+
+        Ok(program
+            .iter()
+            .map(|cell| cell.to_string())
+            .collect::<Vec<_>>()
+            .join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disassemble, Intcode, Memory};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    /// The sample programs from the tests for each day.
+    const SAMPLES: &[&str] = &[
+        "1,0,0,0,99",
+        "3,0,4,0,99",
+        "1002,4,3,4,33",
+        "3,9,8,9,10,9,4,9,99,-1,8",
+        "3,9,7,9,10,9,4,9,99,-1,8",
+        "3,3,1108,-1,8,3,4,3,99",
+        "3,3,1107,-1,8,3,4,3,99",
+        "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+        "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0",
+        "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0",
+        "3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0",
+        "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+        "1102,34915192,34915192,7,4,7,99,0",
+        "104,1125899906842624,99",
+        "203,1,99",
+    ];
+
+    #[test]
+    fn round_trip_samples() {
+        init();
+        for &sample in SAMPLES {
+            let program = Intcode::from(sample).unwrap().program.to_vec();
+            let listing: Vec<String> = disassemble(&program)
+                .iter()
+                .map(|line| line.to_string())
+                .collect();
+            assert_eq!(Ok(sample.to_string()), assemble(&listing.join("\n")));
+        }
+    }
+
+    #[test]
+    fn labels_and_data() {
+        init();
+        let source = "
+            ; Doubles its input.
+            start:  in -> [value]
+                    mul [value], #2, [value]
+                    out [value]
+                    jt #1, #end     ; always
+                    db 1, 2, 3
+            end:    hlt
+            value:  data 0
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!("3,15,1002,15,2,15,4,15,1105,1,14,1,2,3,99,0", program);
+
+        let mut program = Intcode::from(&program).unwrap();
+        program.input.push_back(21);
+        program.run().unwrap();
+        assert_eq!(vec![42], program.output);
+    }
+
+    #[test]
+    fn relative_params() {
+        init();
+        assert_eq!(
+            Ok("109,-3,204,-1,1201,3,5,100,99".to_string()),
+            assemble("arb #-3\nout [rb-1]\nadd [rb+3], #5 -> [100]\nHLT")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        init();
+        assert_eq!(
+            Err(AsmError {
+                line: 2,
+                message: "unknown mnemonic \"nop\"".to_string()
+            }),
+            assemble("hlt\nnop")
+        );
+        assert_eq!(
+            Err(AsmError {
+                line: 1,
+                message: "add can't write to an immediate parameter".to_string()
+            }),
+            assemble("add #1, #2 -> #3")
+        );
+        assert_eq!(
+            Err(AsmError {
+                line: 1,
+                message: "undefined label \"nowhere\"".to_string()
+            }),
+            assemble("jt #1, #nowhere")
+        );
+    }
+}
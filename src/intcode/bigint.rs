@@ -0,0 +1,241 @@
+//! Just enough of an arbitrary-precision integer to run Intcode with: adding,
+//! multiplying, comparing, parsing and printing.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// A signed integer of any size, stored as a sign and a magnitude in base
+/// 2^32 limbs, least significant first. Zero has no limbs and isn't negative.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+/// Adds two magnitudes.
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for ii in 0..a.len().max(b.len()) {
+        let sum = carry + *a.get(ii).unwrap_or(&0) as u64 + *b.get(ii).unwrap_or(&0) as u64;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// Subtracts magnitude b from a, which must be at least as big.
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (ii, &limb) in a.iter().enumerate() {
+        let mut diff = limb as i64 - borrow - *b.get(ii).unwrap_or(&0) as i64;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        result.push(diff as u32);
+    }
+    result
+}
+
+fn cmp_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+impl BigInt {
+    fn new(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let negative = negative && !limbs.is_empty();
+        Self { negative, limbs }
+    }
+
+    /// Multiplies the magnitude by a small number and adds another.
+    fn mul_add_small(&mut self, mul: u32, add: u32) {
+        let mut carry = add as u64;
+        for limb in self.limbs.iter_mut() {
+            let value = *limb as u64 * mul as u64 + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    /// Divides the magnitude by a small number, returning the remainder.
+    fn div_small(&mut self, div: u32) -> u32 {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = (remainder << 32) | *limb as u64;
+            *limb = (value / div as u64) as u32;
+            remainder = value % div as u64;
+        }
+        *self = Self::new(self.negative, std::mem::take(&mut self.limbs));
+        remainder as u32
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let magnitude = value.unsigned_abs();
+        Self::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl std::ops::Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitudes(&self.limbs, &other.limbs));
+        }
+        match cmp_magnitudes(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt::new(other.negative, sub_magnitudes(&other.limbs, &self.limbs)),
+            _ => BigInt::new(self.negative, sub_magnitudes(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl std::ops::Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (ii, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (jj, &b) in other.limbs.iter().enumerate() {
+                let value = limbs[ii + jj] as u64 + a as u64 * b as u64 + carry;
+                limbs[ii + jj] = value as u32;
+                carry = value >> 32;
+            }
+            limbs[ii + other.limbs.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != other.negative, limbs)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitudes(&self.limbs, &other.limbs),
+            (true, true) => cmp_magnitudes(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match input.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+        if digits.is_empty() {
+            return Err(());
+        }
+
+        let mut result = BigInt::default();
+        for c in digits.chars() {
+            result.mul_add_small(10, c.to_digit(10).ok_or(())?);
+        }
+        Ok(BigInt::new(negative, result.limbs))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.limbs.is_empty() {
+            return write!(f, "0");
+        }
+
+        // Peel off nine decimal digits at a time.
+        let mut rest = self.clone();
+        let mut chunks = Vec::new();
+        while !rest.limbs.is_empty() {
+            chunks.push(rest.div_small(1_000_000_000));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in &["0", "7", "-7", "4294967296", "-123456789012345678901234567890"] {
+            assert_eq!(*s, big(s).to_string());
+        }
+        assert_eq!(big("0"), big("-0"));
+        assert!("12a".parse::<BigInt>().is_err());
+        assert!("-".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(big("1000000000000000000000"), &big("999999999999999999999") + &big("1"));
+        assert_eq!(big("-1"), &big("4294967295") + &big("-4294967296"));
+        assert_eq!(big("0"), &big("-12345678901234567890") + &big("12345678901234567890"));
+        assert_eq!(
+            big("-121932631137021795226185032733622923332237463801111263526900"),
+            &big("123456789012345678901234567890") * &big("-987654321098765432109876543210")
+        );
+    }
+
+    #[test]
+    fn compare_and_convert() {
+        assert!(big("-5") < big("3"));
+        assert!(big("-5") < big("-3"));
+        assert!(big("18446744073709551616") > big("18446744073709551615"));
+        assert_eq!(Some(i64::MIN), BigInt::from(i64::MIN).to_i64());
+        assert_eq!(Some(i64::MAX), BigInt::from(i64::MAX).to_i64());
+        assert_eq!(None, big("9223372036854775808").to_i64());
+    }
+}
@@ -0,0 +1,366 @@
+//! Control-flow graphs of Intcode programs, for reverse-engineering them.
+//!
+//! A program is split into basic blocks, starting from address zero and
+//! following every jump whose target is known statically. Targets read from
+//! memory (position or relative mode) can't be followed, so those jumps are
+//! marked as indirect.
+//!
+//! Calls are recognised by the usual pattern: an immediate return address
+//! stored (normally relative to the relative base), then an unconditional
+//! jump, with the return address being the instruction after the jump. Each
+//! call target is treated as the entry of a function, and the return address
+//! is followed as a continuation of the caller.
+
+use super::disasm::DisasmLine;
+use super::isa::{standard_set, InstructionSet};
+use super::{Operation, ParamMode, StaticJump};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Where a jump goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Static(usize),
+    /// Read from memory when the jump is taken.
+    Indirect,
+}
+
+/// How control leaves a block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exit {
+    /// Runs straight on into the next block.
+    FallThrough(usize),
+    /// A jump that's always taken.
+    Jump(Target),
+    /// A jump that's always taken, to a function which returns to `ret`.
+    Call { target: usize, ret: usize },
+    /// A conditional jump.
+    Branch { taken: Target, not_taken: usize },
+    Halt,
+    /// The block runs into something that doesn't decode.
+    Invalid,
+}
+
+/// A run of instructions with a single entry at the top and a single exit at
+/// the bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last cell of the last instruction.
+    pub end: usize,
+    pub lines: Vec<DisasmLine>,
+    pub exit: Exit,
+    /// The entry of the function the block was first reached from.
+    pub function: usize,
+}
+
+impl Block {
+    /// The addresses of the blocks control can go to next, not counting
+    /// indirect jumps.
+    pub fn successors(&self) -> Vec<usize> {
+        match self.exit {
+            Exit::FallThrough(next) | Exit::Jump(Target::Static(next)) => vec![next],
+            Exit::Call { target, ret } => vec![target, ret],
+            Exit::Branch { taken, not_taken } => match taken {
+                Target::Static(taken) => vec![taken, not_taken],
+                Target::Indirect => vec![not_taken],
+            },
+            Exit::Jump(Target::Indirect) | Exit::Halt | Exit::Invalid => Vec::new(),
+        }
+    }
+}
+
+/// A program's control-flow graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    /// Every block found, by start address.
+    pub blocks: BTreeMap<usize, Block>,
+    /// The entries of functions, including the program's own at zero.
+    pub functions: BTreeSet<usize>,
+}
+
+/// Where a jump goes, if it's somewhere in the program.
+fn target(program: &[i64], jump: &StaticJump) -> Target {
+    if jump.mode != ParamMode::Immediate {
+        Target::Indirect
+    } else if 0 <= jump.target && (jump.target as usize) < program.len() {
+        Target::Static(jump.target as usize)
+    } else {
+        // Jumping off the end of the program can't be followed either.
+        Target::Indirect
+    }
+}
+
+impl Cfg {
+    /// Builds the control-flow graph of a program.
+    pub fn new(program: &[i64]) -> Self {
+        standard_set().cfg(program)
+    }
+
+    /// Builds the block starting at an address, which runs until a jump or
+    /// the next leader. `calls` holds the addresses of jumps that are calls.
+    fn block(
+        isa: &InstructionSet,
+        program: &[i64],
+        start: usize,
+        function: usize,
+        leaders: &BTreeSet<usize>,
+        calls: &BTreeSet<usize>,
+    ) -> Block {
+        let mut address = start;
+        let mut lines = Vec::new();
+
+        let exit = loop {
+            let (op, params) = match Operation::decode_in(program, address, isa) {
+                Some(decoded) => decoded,
+                None => break Exit::Invalid,
+            };
+            lines.extend(isa.disassemble_from(program, address, 1));
+            let from = address;
+            let next = address + 1 + op.num_params as usize;
+            address = next;
+
+            if op.halts(params) {
+                break Exit::Halt;
+            }
+            match op.jump(params, isa) {
+                Some(jump) => {
+                    let to = target(program, &jump);
+                    match jump.taken {
+                        None => {
+                            break Exit::Branch {
+                                taken: to,
+                                not_taken: next,
+                            }
+                        }
+                        Some(false) => break Exit::FallThrough(next),
+                        Some(true) => {}
+                    }
+                    match to {
+                        Target::Static(to) if calls.contains(&from) => {
+                            break Exit::Call {
+                                target: to,
+                                ret: next,
+                            }
+                        }
+                        to => break Exit::Jump(to),
+                    }
+                }
+                None if leaders.contains(&next) => break Exit::FallThrough(next),
+                None => {}
+            }
+        };
+
+        Block {
+            start,
+            end: address,
+            lines,
+            exit,
+            function,
+        }
+    }
+
+    /// Renders the graph in Graphviz DOT format, with each function in its
+    /// own cluster.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let node = |start: usize| format!("b{}", start);
+
+        writeln!(dot, "digraph intcode {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for &entry in &self.functions {
+            let label = if entry == 0 { "main".to_string() } else { format!("fn {}", entry) };
+            writeln!(dot, "    subgraph cluster_{} {{", entry).unwrap();
+            writeln!(dot, "        label=\"{}\";", label).unwrap();
+            for block in self.blocks.values().filter(|block| block.function == entry) {
+                let mut text: String = block.lines.iter().map(|line| format!("{}\\l", line)).collect();
+                if block.exit == Exit::Invalid {
+                    text.push_str(&format!("{:04}: ???\\l", block.end));
+                }
+                writeln!(dot, "        {} [label=\"{}\"];", node(block.start), text).unwrap();
+            }
+            writeln!(dot, "    }}").unwrap();
+        }
+
+        let mut indirect = false;
+        for block in self.blocks.values() {
+            let from = node(block.start);
+            let mut edge = |to: Target, attributes: &str| match to {
+                Target::Static(to) => {
+                    writeln!(dot, "    {} -> {}{};", from, node(to), attributes).unwrap()
+                }
+                Target::Indirect => {
+                    indirect = true;
+                    writeln!(dot, "    {} -> indirect [style=dotted];", from).unwrap()
+                }
+            };
+            match block.exit {
+                Exit::FallThrough(next) => edge(Target::Static(next), ""),
+                Exit::Jump(to) => edge(to, ""),
+                Exit::Call { target, ret } => {
+                    edge(Target::Static(target), " [label=\"call\", style=bold]");
+                    edge(Target::Static(ret), " [label=\"return\", style=dashed]");
+                }
+                Exit::Branch { taken, not_taken } => {
+                    edge(taken, " [label=\"taken\"]");
+                    edge(Target::Static(not_taken), " [label=\"not taken\"]");
+                }
+                Exit::Halt | Exit::Invalid => {}
+            }
+        }
+        if indirect {
+            writeln!(dot, "    indirect [shape=ellipse, label=\"indirect\"];").unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+impl InstructionSet {
+    /// Builds the control-flow graph of a program written for this
+    /// instruction set, following whichever instructions it says are jumps.
+    pub fn cfg(&self, program: &[i64]) -> Cfg {
+        let mut cfg = Cfg {
+            blocks: BTreeMap::new(),
+            functions: BTreeSet::new(),
+        };
+        cfg.functions.insert(0);
+
+        // Addresses to explore from.
+        let mut pending = vec![0];
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        let mut calls = BTreeSet::new();
+
+        // First find every address that starts a block, as a jump into the
+        // middle of a block we've already built means splitting it.
+        while let Some(start) = pending.pop() {
+            let mut address = start;
+            let mut constants = Vec::new();
+            while let Some((op, params)) = Operation::decode_in(program, address, self) {
+                let next = address + 1 + op.num_params as usize;
+                constants.extend(op.stored_constant(params));
+                if op.halts(params) {
+                    break;
+                }
+
+                let mut exits = Vec::new();
+                match op.jump(params, self) {
+                    Some(jump) => {
+                        let always = jump.taken == Some(true);
+                        let never = jump.taken == Some(false);
+                        match target(program, &jump) {
+                            Target::Static(to) if always && constants.contains(&(next as i64)) => {
+                                calls.insert(address);
+                                cfg.functions.insert(to);
+                                exits.push(to);
+                                exits.push(next);
+                            }
+                            Target::Static(to) if !never => exits.push(to),
+                            _ => {}
+                        }
+                        if !always {
+                            exits.push(next);
+                        }
+                    }
+                    None => {
+                        address = next;
+                        continue;
+                    }
+                }
+
+                for to in exits {
+                    if leaders.insert(to) {
+                        pending.push(to);
+                    }
+                }
+                break;
+            }
+        }
+
+        // Now build the blocks, in order of address so each is assigned to
+        // the lowest function that reaches it first.
+        let mut owners: BTreeMap<usize, usize> = cfg.functions.iter().map(|&f| (f, f)).collect();
+        let mut pending: Vec<usize> = cfg.functions.iter().rev().copied().collect();
+        while let Some(start) = pending.pop() {
+            if cfg.blocks.contains_key(&start) {
+                continue;
+            }
+            let block = Cfg::block(self, program, start, owners[&start], &leaders, &calls);
+            for next in block.successors() {
+                owners.entry(next).or_insert(block.function);
+                pending.push(next);
+            }
+            cfg.blocks.insert(start, block);
+        }
+
+        cfg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn cfg(source: &str) -> Cfg {
+        Cfg::new(&source.split(',').map(|s| s.parse().unwrap()).collect::<Vec<i64>>())
+    }
+
+    #[test]
+    fn loop_blocks() {
+        init();
+        // Jumps over some data to a loop counting down from 3.
+        let cfg = cfg("1105,1,5,7,7,1001,3,-1,3,1005,3,5,99");
+        assert_eq!(vec![0, 5, 12], cfg.blocks.keys().copied().collect::<Vec<_>>());
+        assert_eq!(Exit::Jump(Target::Static(5)), cfg.blocks[&0].exit);
+        assert_eq!(
+            Exit::Branch {
+                taken: Target::Static(5),
+                not_taken: 12
+            },
+            cfg.blocks[&5].exit
+        );
+        assert_eq!(12, cfg.blocks[&5].end);
+        assert_eq!(2, cfg.blocks[&5].lines.len());
+        assert_eq!(Exit::Halt, cfg.blocks[&12].exit);
+    }
+
+    #[test]
+    fn splits_blocks_at_jump_targets() {
+        init();
+        // The loop jumps back into the middle of the straight-line code.
+        let cfg = cfg("1101,0,3,20,1001,20,-1,20,1005,20,4,99");
+        assert_eq!(vec![0, 4, 11], cfg.blocks.keys().copied().collect::<Vec<_>>());
+        assert_eq!(Exit::FallThrough(4), cfg.blocks[&0].exit);
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        init();
+        // Calls a function at 10, which returns through the stack.
+        let cfg = cfg("109,20,21101,9,0,0,1105,1,10,99,104,1,2106,0,0");
+        assert_eq!(vec![0, 10], cfg.functions.iter().copied().collect::<Vec<_>>());
+        assert_eq!(Exit::Call { target: 10, ret: 9 }, cfg.blocks[&0].exit);
+        assert_eq!(Exit::Jump(Target::Indirect), cfg.blocks[&10].exit);
+        assert_eq!(10, cfg.blocks[&10].function);
+        assert_eq!(0, cfg.blocks[&9].function);
+    }
+
+    #[test]
+    fn dot_output() {
+        init();
+        let dot = cfg("109,20,21101,9,0,0,1105,1,10,99,104,1,2106,0,0").to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    subgraph cluster_10 {\n        label=\"fn 10\";\n"));
+        assert!(dot.contains("        b10 [label=\"0010: OUT #1\\l0012: JF #0, [rb+0]\\l\"];\n"));
+        assert!(dot.contains("    b0 -> b10 [label=\"call\", style=bold];\n"));
+        assert!(dot.contains("    b0 -> b9 [label=\"return\", style=dashed];\n"));
+        assert!(dot.contains("    b10 -> indirect [style=dotted];\n"));
+    }
+}
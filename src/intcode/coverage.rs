@@ -0,0 +1,262 @@
+//! Coverage of a run: which addresses were executed, read and written.
+
+use super::{InstructionSet, Intcode};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Cells shown per row in a coverage map.
+const MAP_WIDTH: usize = 50;
+
+/// What a run did with each address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// Addresses of the instructions executed.
+    pub executed: BTreeSet<i64>,
+    /// Parameter cells of the instructions executed.
+    pub operands: BTreeSet<i64>,
+    /// Addresses read by position or relative parameters.
+    pub read: BTreeSet<i64>,
+    pub written: BTreeSet<i64>,
+}
+
+impl Coverage {
+    /// Notes the addresses used by an instruction that has just executed.
+    pub(super) fn record(&mut self, pc: i64, num_params: i64, reads: &[i64], write: Option<i64>) {
+        self.executed.insert(pc);
+        self.operands.extend(pc + 1..=pc + num_params);
+        self.read.extend(reads);
+        self.written.extend(write);
+    }
+
+    /// The symbol for an address in a coverage map:
+    ///
+    /// - `X` an instruction executed, `x` one of its parameters;
+    /// - `!` either of those, but also written to (self-modifying code);
+    /// - `R` read, `W` written, `B` both;
+    /// - `.` not used at all.
+    pub fn symbol(&self, address: i64) -> char {
+        let code = self.executed.contains(&address) || self.operands.contains(&address);
+        match (code, self.read.contains(&address), self.written.contains(&address)) {
+            (true, _, true) => '!',
+            (true, _, false) if self.executed.contains(&address) => 'X',
+            (true, _, false) => 'x',
+            (false, true, true) => 'B',
+            (false, true, false) => 'R',
+            (false, false, true) => 'W',
+            (false, false, false) => '.',
+        }
+    }
+
+    /// The highest address used, if any.
+    pub fn max_address(&self) -> Option<i64> {
+        let sets = [&self.executed, &self.operands, &self.read, &self.written];
+        sets.iter().filter_map(|set| set.iter().next_back()).max().copied()
+    }
+
+    /// A compact map of the first `len` addresses, one symbol per address.
+    pub fn map(&self, len: usize) -> String {
+        let mut map = String::new();
+        for row in (0..len).step_by(MAP_WIDTH) {
+            write!(map, "{:04}:", row).unwrap();
+            for address in row..len.min(row + MAP_WIDTH) {
+                if address % 10 == 0 {
+                    map.push(' ');
+                }
+                map.push(self.symbol(address as i64));
+            }
+            map.push('\n');
+        }
+        map
+    }
+
+    /// Lists the addresses used at or beyond `len`, which a map of the program
+    /// doesn't cover. A relative write can land anywhere, so they're given as
+    /// runs of consecutive addresses with the same symbol rather than a map.
+    pub fn beyond(&self, len: usize) -> String {
+        let sets = [&self.executed, &self.operands, &self.read, &self.written];
+        let addresses: BTreeSet<i64> = sets.iter().flat_map(|set| set.range(len as i64..)).copied().collect();
+
+        let mut runs: Vec<(i64, i64, char)> = Vec::new();
+        for address in addresses {
+            let symbol = self.symbol(address);
+            match runs.last_mut() {
+                Some((_, end, s)) if *end + 1 == address && *s == symbol => *end = address,
+                _ => runs.push((address, address, symbol)),
+            }
+        }
+
+        let mut list = String::new();
+        for (start, end, symbol) in runs {
+            if start == end {
+                writeln!(list, "{:04}: {}", start, symbol).unwrap();
+            } else {
+                writeln!(list, "{:04}-{:04}: {}", start, end, symbol).unwrap();
+            }
+        }
+        list
+    }
+
+    /// A disassembly of `program` (as it was before running) with the
+    /// instruction set it ran with, each line marked `X` if it was executed,
+    /// then `R` and `W` if any of its cells were read or written.
+    ///
+    /// The disassembly is a linear sweep, so instructions that were only ever
+    /// executed after being patched in won't line up with it; `map` shows
+    /// those.
+    pub fn annotate(&self, isa: &InstructionSet, program: &[i64]) -> String {
+        let mut listing = String::new();
+        for line in isa.disassemble(program) {
+            let cells = line.address as i64..(line.address + line.cells.len()) as i64;
+            let mark = |set: &BTreeSet<i64>, c| if set.range(cells.clone()).next().is_some() { c } else { ' ' };
+            writeln!(
+                listing,
+                "{}{}{} {}",
+                mark(&self.executed, 'X'),
+                mark(&self.read, 'R'),
+                mark(&self.written, 'W'),
+                line
+            )
+            .unwrap();
+        }
+        listing
+    }
+
+    /// A one-line summary of the run's coverage.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} instructions executed, {} cells read, {} written, {} both code and written",
+            self.executed.len(),
+            self.read.len(),
+            self.written.len(),
+            self.executed.union(&self.operands).filter(|a| self.written.contains(a)).count()
+        )
+    }
+
+    /// Lists the instructions executed in this run but not in `other`, with
+    /// their disassembly from `program`.
+    pub fn executed_only_here(&self, other: &Coverage, isa: &InstructionSet, program: &[i64]) -> Vec<String> {
+        self.executed
+            .difference(&other.executed)
+            .map(|&address| match isa.disassemble_from(program, address as usize, 1).pop() {
+                Some(line) if line.address == address as usize => line.to_string(),
+                _ => format!("{:04}: ?", address),
+            })
+            .collect()
+    }
+}
+
+impl<I, O, M> Intcode<I, O, M> {
+    /// Starts measuring coverage, throwing away any measured so far.
+    pub fn start_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    /// Stops measuring coverage, and returns what was measured.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Effect, Instruction, Memory};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // From day 5: outputs 999, 1000 or 1001 as the input is below, equal to
+    // or above 8, each from a different path.
+    const COMPARE: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+
+    fn coverage(source: &str, input: i64) -> Coverage {
+        coverage_with(InstructionSet::standard(), source, input)
+    }
+
+    fn coverage_with(isa: InstructionSet, source: &str, input: i64) -> Coverage {
+        let mut program = Intcode::from(source).unwrap();
+        program.set_instruction_set(isa);
+        program.input.push_back(input);
+        program.start_coverage();
+        program.run().unwrap();
+        program.take_coverage().unwrap()
+    }
+
+    #[test]
+    fn records_usage() {
+        init();
+        let coverage = coverage("3,9,8,9,10,9,4,9,99,-1,8", 8);
+        assert_eq!(vec![0, 2, 6, 8], coverage.executed.iter().copied().collect::<Vec<_>>());
+        assert_eq!(vec![9, 10], coverage.read.iter().copied().collect::<Vec<_>>());
+        assert_eq!(vec![9], coverage.written.iter().copied().collect::<Vec<_>>());
+        assert_eq!(Some(10), coverage.max_address());
+        assert_eq!("0000: XxXxxxXxXB R\n", coverage.map(11));
+        assert_eq!("", coverage.beyond(11));
+    }
+
+    #[test]
+    fn beyond_the_program() {
+        init();
+        // Writes 7 at 10^12 and 10^12 + 1, then reads it back.
+        let coverage = coverage("3,0,109,1000000000000,21101,3,4,0,21101,3,4,1,204,1,99", 0);
+        assert_eq!(Some(1_000_000_000_001), coverage.max_address());
+        assert_eq!("1000000000000: W\n1000000000001: B\n", coverage.beyond(15));
+        assert_eq!(1, coverage.map(15).lines().count());
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        init();
+        let coverage = coverage("3,3,1108,-1,8,3,4,3,99", 8);
+        assert_eq!('!', coverage.symbol(3));
+        assert!(coverage.summary().ends_with("1 both code and written"));
+    }
+
+    #[test]
+    fn annotated_listing() {
+        init();
+        let program = Intcode::from("3,9,8,9,10,9,4,9,99,-1,8").unwrap().program.to_vec();
+        let listing = coverage("3,9,8,9,10,9,4,9,99,-1,8", 8).annotate(&InstructionSet::standard(), &program);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!("X   0000: IN -> [9]", lines[0]);
+        assert_eq!("X   0008: HLT", lines[3]);
+        assert_eq!(" RW 0009: DATA -1", lines[4]);
+    }
+
+    #[test]
+    fn dialect_listing() {
+        init();
+        let dialect = InstructionSet::standard().with(Instruction::new(10, "SUB", 3, Some(2), |p| {
+            p[0].checked_sub(p[1]).map(Effect::Store)
+        }));
+        let source = "1110,10,3,7,4,7,99,0";
+        let program = Intcode::from(source).unwrap().program.to_vec();
+        let listing = coverage_with(dialect.clone(), source, 0).annotate(&dialect, &program);
+        assert_eq!("X   0000: SUB #10, #3 -> [7]", listing.lines().next().unwrap());
+        assert_eq!(
+            vec!["0000: SUB #10, #3 -> [7]", "0004: OUT [7]", "0006: HLT"],
+            coverage_with(dialect.clone(), source, 0).executed_only_here(&Coverage::default(), &dialect, &program)
+        );
+    }
+
+    #[test]
+    fn compare_runs() {
+        init();
+        let program = Intcode::from(COMPARE).unwrap().program.to_vec();
+        let below = coverage(COMPARE, 7);
+        assert_eq!(
+            vec!["0022: MUL [21], #125 -> [20]", "0026: OUT [20]", "0028: JT #1, #46"],
+            coverage(COMPARE, 8).executed_only_here(&below, &InstructionSet::standard(), &program)
+        );
+        assert_eq!(
+            vec![
+                "0016: JF #0, #36",
+                "0036: ADD #1000, #1 -> [20]",
+                "0040: OUT [20]",
+                "0042: JT #1, #46"
+            ],
+            coverage(COMPARE, 9).executed_only_here(&below, &InstructionSet::standard(), &program)
+        );
+    }
+}
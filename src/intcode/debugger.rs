@@ -0,0 +1,258 @@
+use super::disasm::DisasmLine;
+use super::{offset_address, Intcode, IntcodeError, Memory, Operation, StepResult, WatchHit, Watchpoint, MAX_PARAMS};
+use std::collections::BTreeSet;
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// A single step (or `next`) finished normally.
+    Stepped,
+    /// The pc reached a breakpoint.
+    Breakpoint(i64),
+    /// An instruction touched a watched address.
+    Watchpoint(WatchHit),
+    /// The program wants input that hasn't been pushed yet.
+    NeedInput,
+    /// The program has halted.
+    Halted,
+}
+
+/// Wraps an Intcode machine so it can be run under control of breakpoints
+/// and single-stepped, with its memory and registers open to inspection.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub machine: Intcode,
+    breakpoints: BTreeSet<i64>,
+}
+
+impl Debugger {
+    pub fn new(machine: Intcode) -> Self {
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: i64) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Removes a breakpoint, returning whether there was one to remove.
+    pub fn remove_breakpoint(&mut self, address: i64) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &i64> {
+        self.breakpoints.iter()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.machine.add_watchpoint(watchpoint);
+    }
+
+    /// Removes all watchpoints on an address, returning whether there were
+    /// any.
+    pub fn remove_watchpoints(&mut self, address: i64) -> bool {
+        self.machine.remove_watchpoints(address)
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<Stop, IntcodeError> {
+        Ok(match self.machine.step()? {
+            StepResult::Halt => Stop::Halted,
+            StepResult::NeedInput => Stop::NeedInput,
+            StepResult::Watchpoint(hit) => Stop::Watchpoint(hit),
+            StepResult::Continue | StepResult::Output => Stop::Stepped,
+        })
+    }
+
+    /// Runs until the instruction after the current one is reached, so that
+    /// a jump into a subroutine runs the whole subroutine. Breakpoints still
+    /// stop execution on the way.
+    // Named after the debugger command, not Iterator::next.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Stop, IntcodeError> {
+        let pc = self.machine.pc();
+        let op = Operation::from(&self.machine, pc)?;
+        let target = offset_address(pc, op.num_params + 1, pc)?;
+
+        match self.step()? {
+            Stop::Stepped => {}
+            stop => return Ok(stop),
+        }
+        while self.machine.pc() != target {
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return Ok(Stop::Breakpoint(self.machine.pc()));
+            }
+            match self.step()? {
+                Stop::Stepped => {}
+                stop => return Ok(stop),
+            }
+        }
+
+        Ok(Stop::Stepped)
+    }
+
+    /// Runs until a breakpoint is hit, or the program halts or needs input.
+    /// A breakpoint at the current pc doesn't count, so that continuing from
+    /// a breakpoint makes progress.
+    pub fn cont(&mut self) -> Result<Stop, IntcodeError> {
+        loop {
+            match self.step()? {
+                Stop::Stepped => {}
+                stop => return Ok(stop),
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return Ok(Stop::Breakpoint(self.machine.pc()));
+            }
+        }
+    }
+
+    /// Undoes the last instruction, if history is being kept.
+    pub fn step_back(&mut self) -> bool {
+        self.machine.step_back()
+    }
+
+    /// Steps backwards to just before the last write to `address`, if history
+    /// is being kept.
+    pub fn rewind_to_write(&mut self, address: i64) -> bool {
+        self.machine.rewind_to_write(address)
+    }
+
+    /// Reads `len` cells of memory starting at `start`.
+    pub fn memory(&self, start: i64, len: usize) -> Result<Vec<i64>, IntcodeError> {
+        let end = offset_address(start, len as i64, self.machine.pc())?;
+        (start..end)
+            .map(|address| self.machine.mem_get(address))
+            .collect()
+    }
+
+    pub fn set_memory(&mut self, address: i64, value: i64) -> Result<(), IntcodeError> {
+        self.machine.mem_set(address, value)
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.machine.input.push_back(value);
+    }
+
+    /// Takes any output produced since the last call.
+    pub fn take_output(&mut self) -> Vec<i64> {
+        self.machine.output.drain(..).collect()
+    }
+
+    /// Disassembles `count` instructions starting at the current pc.
+    ///
+    /// Only the cells the listing can cover are read, as memory can reach far
+    /// beyond the image.
+    pub fn listing(&self, count: usize) -> Vec<DisasmLine> {
+        let pc = self.machine.pc() as usize;
+        let memory = &self.machine.program;
+        let end = memory.extent().min(pc.saturating_add(count.saturating_mul(MAX_PARAMS + 1)));
+        let window: Vec<i64> = (pc..end).map(|address| memory.load(address)).collect();
+
+        let mut lines = self.machine.instruction_set().disassemble_from(&window, 0, count);
+        for line in &mut lines {
+            line.address += pc;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        init();
+        let program = Intcode::from("1,0,0,0,1,0,0,0,4,0,99").unwrap();
+        let mut debugger = Debugger::new(program);
+        debugger.add_breakpoint(8);
+
+        assert_eq!(Ok(Stop::Stepped), debugger.step());
+        assert_eq!(4, debugger.machine.pc());
+        assert_eq!(Ok(vec![2]), debugger.memory(0, 1));
+
+        assert_eq!(Ok(Stop::Breakpoint(8)), debugger.cont());
+        debugger.set_memory(0, 77).unwrap();
+        assert_eq!(Ok(Stop::Halted), debugger.cont());
+        assert_eq!(vec![77], debugger.take_output());
+    }
+
+    #[test]
+    fn next_steps_over_subroutine() {
+        init();
+        // Store a return address, jump to a subroutine which outputs 5, then
+        // jump back to the return address and halt.
+        let program = Intcode::from("1101,0,7,20,1105,1,9,99,0,104,5,106,0,20").unwrap();
+        let mut debugger = Debugger::new(program);
+        debugger.step().unwrap();
+
+        assert_eq!(Ok(Stop::Stepped), debugger.next());
+        assert_eq!(7, debugger.machine.pc());
+        assert_eq!(vec![5], debugger.take_output());
+    }
+
+    #[test]
+    fn end_of_memory() {
+        init();
+        // Jump to the very top of memory, where there's no room for the
+        // instruction after an add.
+        let top = i64::MAX - 1;
+        let mut debugger = Debugger::new(Intcode::from(&format!("1106,0,{}", top)).unwrap());
+        debugger.set_memory(top, 1101).unwrap();
+        debugger.step().unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: top }), debugger.next());
+        assert_eq!(Err(IntcodeError::Overflow { pc: top }), debugger.memory(top, 5));
+    }
+
+    #[test]
+    fn stops_at_watchpoint() {
+        init();
+        let mut debugger = Debugger::new(Intcode::from("1,0,0,0,1,0,0,0,99").unwrap());
+        debugger.add_watchpoint(Watchpoint::write(0).when(|value| value == 4));
+        match debugger.cont() {
+            Ok(Stop::Watchpoint(hit)) => assert_eq!(4, hit.pc),
+            stop => panic!("Unexpected stop {:?}", stop),
+        }
+        assert_eq!(Ok(Stop::Halted), debugger.cont());
+    }
+
+    #[test]
+    fn reverse_stepping() {
+        init();
+        let mut debugger = Debugger::new(Intcode::from("1,0,0,0,1,0,0,0,99").unwrap());
+        debugger.machine.start_history();
+        assert_eq!(Ok(Stop::Halted), debugger.cont());
+        assert!(debugger.rewind_to_write(0));
+        assert_eq!(4, debugger.machine.pc());
+        assert!(debugger.step_back());
+        assert_eq!(Ok(vec![1]), debugger.memory(0, 1));
+        assert!(!debugger.step_back());
+    }
+
+    #[test]
+    fn listing_after_huge_write() {
+        init();
+        let program = Intcode::from("109,1000000000000,21101,3,4,0,204,0,99").unwrap();
+        let mut debugger = Debugger::new(program);
+        debugger.step().unwrap();
+        debugger.step().unwrap();
+        let lines: Vec<String> = debugger.listing(3).iter().map(|line| line.to_string()).collect();
+        assert_eq!(vec!["0006: OUT [rb+0]", "0008: HLT", "0009: DATA 0"], lines);
+    }
+
+    #[test]
+    fn waits_for_pushed_input() {
+        init();
+        let mut debugger = Debugger::new(Intcode::from("3,0,4,0,99").unwrap());
+        assert_eq!(Ok(Stop::NeedInput), debugger.cont());
+        debugger.push_input(9);
+        assert_eq!(Ok(Stop::Halted), debugger.cont());
+        assert_eq!(vec![9], debugger.take_output());
+    }
+}
@@ -0,0 +1,152 @@
+use super::isa::{standard_set, InstructionSet};
+use super::{Operation, ParamMode};
+use std::fmt;
+
+/// A single line of disassembly: either a decoded instruction, or a cell that
+/// doesn't decode and is shown as DATA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisasmLine {
+    pub address: usize,
+    pub cells: Vec<i64>,
+    pub text: String,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: {}", self.address, self.text)
+    }
+}
+
+/// Formats a parameter with a sigil for its mode: `#5` is immediate, `[5]` is
+/// position and `[rb+5]` is relative to the relative base.
+fn format_param(mode: &ParamMode, value: i64) -> String {
+    match mode {
+        ParamMode::Immediate => format!("#{}", value),
+        ParamMode::Position | ParamMode::Reference => format!("[{}]", value),
+        ParamMode::Relative | ParamMode::RelativeReference => {
+            if value < 0 {
+                format!("[rb{}]", value)
+            } else {
+                format!("[rb+{}]", value)
+            }
+        }
+    }
+}
+
+/// Decodes the instruction at `address`, if there's a complete one there.
+fn decode_line(isa: &InstructionSet, program: &[i64], address: usize) -> Option<DisasmLine> {
+    let (op, params) = Operation::decode_in(program, address, isa)?;
+    let cells = program[address..=address + params.len()].to_vec();
+    let mut reads = Vec::new();
+    let mut write = None;
+    for (mode, &value) in op.param_modes().iter().zip(params) {
+        match mode {
+            ParamMode::Reference | ParamMode::RelativeReference => {
+                write = Some(format_param(mode, value))
+            }
+            _ => reads.push(format_param(mode, value)),
+        }
+    }
+
+    let mut text = op.mnemonic(isa).to_string();
+    if !reads.is_empty() {
+        text.push(' ');
+        text.push_str(&reads.join(", "));
+    }
+    if let Some(write) = write {
+        text.push_str(" -> ");
+        text.push_str(&write);
+    }
+
+    Some(DisasmLine {
+        address,
+        cells,
+        text,
+    })
+}
+
+/// Walks a program from the start, decoding each instruction in turn. Cells
+/// that don't decode to a complete instruction are listed as DATA.
+///
+/// This is a straight linear sweep, so data that happens to look like an
+/// instruction will be shown as one.
+pub fn disassemble(program: &[i64]) -> Vec<DisasmLine> {
+    standard_set().disassemble(program)
+}
+
+/// Disassembles at most `count` lines, starting from `start` rather than the
+/// beginning of the program.
+pub fn disassemble_from(program: &[i64], start: usize, count: usize) -> Vec<DisasmLine> {
+    standard_set().disassemble_from(program, start, count)
+}
+
+impl InstructionSet {
+    /// Disassembles a program written for this instruction set, in the same
+    /// way as `disassemble`.
+    pub fn disassemble(&self, program: &[i64]) -> Vec<DisasmLine> {
+        self.disassemble_from(program, 0, program.len())
+    }
+
+    /// Disassembles at most `count` lines of a program written for this
+    /// instruction set, starting from `start`.
+    pub fn disassemble_from(&self, program: &[i64], start: usize, count: usize) -> Vec<DisasmLine> {
+        let mut lines = Vec::new();
+        let mut address = start;
+
+        while address < program.len() && lines.len() < count {
+            let line = decode_line(self, program, address).unwrap_or_else(|| DisasmLine {
+                address,
+                cells: vec![program[address]],
+                text: format!("DATA {}", program[address]),
+            });
+            address += line.cells.len();
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn listing(program: &[i64]) -> Vec<String> {
+        disassemble(program).iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn disassemble_day_five() {
+        init();
+        assert_eq!(
+            vec!["0000: MUL [4], #3 -> [4]", "0004: DATA 33"],
+            listing(&[1002, 4, 3, 4, 33])
+        );
+    }
+
+    #[test]
+    fn disassemble_relative() {
+        init();
+        assert_eq!(
+            vec![
+                "0000: ARB #1",
+                "0002: OUT [rb-1]",
+                "0004: ADD [rb+3], #5 -> [100]",
+                "0008: IN -> [rb+2]",
+                "0010: JF #0, [7]",
+                "0013: HLT",
+            ],
+            listing(&[109, 1, 204, -1, 1201, 3, 5, 100, 203, 2, 106, 0, 7, 99])
+        );
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        init();
+        assert_eq!(vec!["0000: DATA 1", "0001: DATA 2"], listing(&[1, 2]));
+    }
+}
@@ -0,0 +1,131 @@
+//! Recording enough history to run a program backwards.
+
+use super::{Intcode, Memory};
+
+/// What's needed to undo a single instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// The pc and relative base before the instruction ran.
+    pub pc: i64,
+    pub relative_base: i64,
+    /// The address written, and the value it held before.
+    pub overwritten: Option<(i64, i64)>,
+    /// The input consumed, if any.
+    pub input: Option<i64>,
+    /// Whether an output was pushed.
+    pub output: bool,
+}
+
+impl<I, O, M> Intcode<I, O, M> {
+    /// Starts recording history, so that instructions run from now on can be
+    /// undone. This costs memory for every instruction executed.
+    pub fn start_history(&mut self) {
+        self.history = Some(Vec::new());
+    }
+
+    /// Stops recording history, and forgets what has been recorded.
+    pub fn stop_history(&mut self) {
+        self.history = None;
+    }
+
+    /// How many instructions can be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, Vec::len)
+    }
+}
+
+impl Intcode {
+    /// Undoes the last instruction executed, returning false if there's no
+    /// history to undo. Input it consumed is put back at the front of the
+    /// input, and output it pushed is removed if still there.
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.history.as_mut().and_then(Vec::pop) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        debug!("Undoing {:?}", entry);
+
+        if let Some((address, value)) = entry.overwritten {
+            self.program.store(address as usize, value);
+        }
+        if let Some(input) = entry.input {
+            self.input.push_front(input);
+        }
+        if entry.output {
+            self.output.pop();
+        }
+        self.pc = entry.pc;
+        self.relative_base = entry.relative_base;
+
+        true
+    }
+
+    /// Steps backwards until just before the last instruction that wrote to
+    /// `address`, returning false (with all history undone) if none did.
+    pub fn rewind_to_write(&mut self, address: i64) -> bool {
+        loop {
+            let wrote = match self.history.as_ref().and_then(|h| h.last()) {
+                Some(entry) => entry.overwritten.map(|(a, _)| a) == Some(address),
+                None => return false,
+            };
+            self.step_back();
+            if wrote {
+                return true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn step_back() {
+        init();
+        let mut program = Intcode::from("3,0,1,0,0,0,4,0,99").unwrap();
+        program.input.push_back(3);
+        program.start_history();
+        program.run().unwrap();
+        assert_eq!(vec![6], program.output);
+        assert_eq!(4, program.history_len());
+
+        // Undo the halt and the output.
+        assert!(program.step_back());
+        assert!(program.step_back());
+        assert_eq!(6, program.pc);
+        assert!(program.output.is_empty());
+
+        // Undo the add, then the input.
+        assert!(program.step_back());
+        assert_eq!(3, program.program[0]);
+        assert!(program.step_back());
+        assert_eq!(vec![3, 0, 1, 0, 0, 0, 4, 0, 99], program.program);
+        assert_eq!(vec![3], program.input.iter().cloned().collect::<Vec<_>>());
+        assert!(!program.step_back());
+
+        // Running forwards again gives the same answer.
+        program.run().unwrap();
+        assert_eq!(vec![6], program.output);
+    }
+
+    #[test]
+    fn rewind_to_write() {
+        init();
+        let mut program = Intcode::from("1,0,0,0,1,0,0,0,1101,1,1,20,99").unwrap();
+        program.start_history();
+        program.run().unwrap();
+
+        assert!(program.rewind_to_write(0));
+        assert_eq!(4, program.pc);
+        assert_eq!(2, program.program[0]);
+
+        assert!(program.rewind_to_write(0));
+        assert_eq!(0, program.pc);
+        assert!(!program.rewind_to_write(0));
+    }
+}
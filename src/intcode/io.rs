@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+/// Somewhere an Intcode program can take its input from.
+pub trait InputSource {
+    /// Returns the next input value, or None if there isn't one (yet).
+    fn next_input(&mut self) -> Option<i64>;
+}
+
+/// Somewhere an Intcode program can send its output to.
+pub trait OutputSink {
+    fn push_output(&mut self, value: i64);
+}
+
+impl InputSource for VecDeque<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl OutputSink for VecDeque<i64> {
+    fn push_output(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl OutputSink for Vec<i64> {
+    fn push_output(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// Any closure producing values can be used as input...
+impl<F: FnMut() -> Option<i64>> InputSource for F {
+    fn next_input(&mut self) -> Option<i64> {
+        self()
+    }
+}
+
+/// ...and any closure taking values can be used as output.
+impl<F: FnMut(i64)> OutputSink for F {
+    fn push_output(&mut self, value: i64) {
+        self(value)
+    }
+}
+
+/// Receiving from a channel blocks until a value arrives. There's no more
+/// input once every sender has gone away.
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Output sent after the receiver has gone away is dropped.
+impl OutputSink for Sender<i64> {
+    fn push_output(&mut self, value: i64) {
+        if self.send(value).is_err() {
+            debug!("Receiver hung up; dropping {}", value);
+        }
+    }
+}
+
+/// Reads input as text, one number per line. Lines that aren't numbers are
+/// skipped; the input is finished at end of file.
+#[derive(Debug)]
+pub struct TextInput<R> {
+    reader: R,
+}
+
+impl<R: BufRead> TextInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl TextInput<std::io::BufReader<std::io::Stdin>> {
+    pub fn stdin() -> Self {
+        Self::new(std::io::BufReader::new(std::io::stdin()))
+    }
+}
+
+impl<R: BufRead> InputSource for TextInput<R> {
+    fn next_input(&mut self) -> Option<i64> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => match line.trim().parse() {
+                    Ok(value) => return Some(value),
+                    Err(_) => warn!("Ignoring non-numeric input {:?}", line.trim()),
+                },
+            }
+        }
+    }
+}
+
+/// Writes output as text, one number per line.
+#[derive(Debug)]
+pub struct TextOutput<W> {
+    writer: W,
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl TextOutput<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl<W: Write> OutputSink for TextOutput<W> {
+    fn push_output(&mut self, value: i64) {
+        writeln!(self.writer, "{}", value).expect("Unable to write output");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Intcode;
+    use std::sync::mpsc::channel;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn closures_as_io() {
+        init();
+        let mut inputs = vec![3, 2, 1];
+        let mut outputs = Vec::new();
+        Intcode::from("3,0,4,0,3,0,4,0,99")
+            .unwrap()
+            .with_io(|| inputs.pop(), |value| outputs.push(value * 10))
+            .run()
+            .unwrap();
+        assert_eq!(vec![10, 20], outputs);
+    }
+
+    #[test]
+    fn channels_as_io() {
+        init();
+        let (input_tx, input_rx) = channel();
+        let (output_tx, output_rx) = channel();
+        input_tx.send(42).unwrap();
+        drop(input_tx);
+
+        let mut program = Intcode::from("3,0,4,0,99").unwrap().with_io(input_rx, output_tx);
+        program.run().unwrap();
+        assert_eq!(Ok(42), output_rx.recv());
+    }
+
+    #[test]
+    fn text_io() {
+        init();
+        let mut written = Vec::new();
+        let input = TextInput::new("five\n5\n".as_bytes());
+        let output = TextOutput::new(&mut written);
+        Intcode::from("3,0,4,0,99").unwrap().with_io(input, output).run().unwrap();
+        assert_eq!("5\n", String::from_utf8(written).unwrap());
+    }
+}
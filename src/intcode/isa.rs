@@ -0,0 +1,485 @@
+//! Instruction sets: which opcodes there are, what they're called, how many
+//! parameters they take and what they do.
+//!
+//! The VM, assembler and disassembler all work from an `InstructionSet`, so a
+//! dialect with more (or fewer) instructions only has to be defined once:
+//!
+//! ```ignore
+//! let sub = Instruction::new(10, "SUB", 3, Some(2), |p| p[0].checked_sub(p[1]).map(Effect::Store));
+//! let dialect = InstructionSet::standard().with(sub);
+//! let program = dialect.assemble("sub #10, #3 -> [0]\nhlt")?;
+//! ```
+
+use super::{Opcode, MAX_PARAMS};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+/// Opcodes are the last two digits of an instruction.
+const OPCODES: usize = 100;
+
+/// What an instruction does, worked out from its parameters. The machine
+/// carries it out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Nothing beyond moving on to the next instruction.
+    Nothing,
+    /// Stores a value at the address in the write parameter.
+    Store(i64),
+    /// Stores the next input at the address in the write parameter, or waits
+    /// for some if there isn't any yet.
+    Input,
+    Output(i64),
+    /// Carries on from an address rather than the next instruction.
+    Jump(i64),
+    /// Adds to the relative base.
+    AdjustBase(i64),
+    Halt,
+}
+
+/// Works out what an instruction does from its parameters, as read according
+/// to their modes (so a write parameter is the address to write to). Returns
+/// None if a result doesn't fit in a cell.
+pub type Execute = fn(&[i64]) -> Option<Effect>;
+
+/// A single instruction in an instruction set.
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
+    pub code: i64,
+    /// The name shown in listings. The assembler ignores case.
+    pub mnemonic: &'static str,
+    pub num_params: usize,
+    /// The parameter written to, if any. Only instructions with one may have
+    /// a Store or Input effect; the VM stops with `NoWriteParameter` if an
+    /// instruction without one does.
+    pub writes: Option<usize>,
+    /// The parameter holding the target, if it's a jump. Tools that follow
+    /// control flow without running the program use it; the VM doesn't.
+    pub jumps: Option<usize>,
+    pub execute: Execute,
+    // Which standard instruction this is, for the tools that need to know
+    // what an instruction means rather than just run it.
+    pub(super) opcode: Opcode,
+}
+
+impl Instruction {
+    /// Defines an instruction. Panics if the code isn't two digits, or the
+    /// parameters don't make sense.
+    pub fn new(
+        code: i64,
+        mnemonic: &'static str,
+        num_params: usize,
+        writes: Option<usize>,
+        execute: Execute,
+    ) -> Self {
+        assert!((0..OPCODES as i64).contains(&code), "{} isn't a two-digit opcode", code);
+        assert!(num_params <= MAX_PARAMS, "{} takes too many parameters", mnemonic);
+        assert!(writes.is_none_or(|w| w < num_params), "{} writes to a parameter it doesn't have", mnemonic);
+        Self {
+            code,
+            mnemonic,
+            num_params,
+            writes,
+            jumps: None,
+            execute,
+            opcode: Opcode::Custom,
+        }
+    }
+
+    /// Marks the instruction as a jump, with its target in parameter
+    /// `target`. Whether it's taken should depend only on the others.
+    pub fn jumps_to(self, target: usize) -> Self {
+        assert!(target < self.num_params, "{} jumps to a parameter it doesn't have", self.mnemonic);
+        assert!(self.writes != Some(target), "{} jumps to the parameter it writes", self.mnemonic);
+        Self {
+            jumps: Some(target),
+            ..self
+        }
+    }
+
+    fn standard(
+        opcode: Opcode,
+        code: i64,
+        mnemonic: &'static str,
+        num_params: usize,
+        writes: Option<usize>,
+        execute: Execute,
+    ) -> Self {
+        Self {
+            opcode,
+            ..Self::new(code, mnemonic, num_params, writes, execute)
+        }
+    }
+}
+
+fn add(p: &[i64]) -> Option<Effect> {
+    p[0].checked_add(p[1]).map(Effect::Store)
+}
+
+fn multiply(p: &[i64]) -> Option<Effect> {
+    p[0].checked_mul(p[1]).map(Effect::Store)
+}
+
+fn input(_: &[i64]) -> Option<Effect> {
+    Some(Effect::Input)
+}
+
+fn output(p: &[i64]) -> Option<Effect> {
+    Some(Effect::Output(p[0]))
+}
+
+fn jump_if_true(p: &[i64]) -> Option<Effect> {
+    Some(if p[0] != 0 { Effect::Jump(p[1]) } else { Effect::Nothing })
+}
+
+fn jump_if_false(p: &[i64]) -> Option<Effect> {
+    Some(if p[0] == 0 { Effect::Jump(p[1]) } else { Effect::Nothing })
+}
+
+fn less_than(p: &[i64]) -> Option<Effect> {
+    Some(Effect::Store((p[0] < p[1]) as i64))
+}
+
+fn equals(p: &[i64]) -> Option<Effect> {
+    Some(Effect::Store((p[0] == p[1]) as i64))
+}
+
+fn update_base(p: &[i64]) -> Option<Effect> {
+    Some(Effect::AdjustBase(p[0]))
+}
+
+fn halt(_: &[i64]) -> Option<Effect> {
+    Some(Effect::Halt)
+}
+
+/// How far the puzzles had got with Intcode. Each level runs everything the
+/// ones before it could.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum FeatureLevel {
+    /// ADD, MUL and HLT, with position parameters only.
+    Day2,
+    /// Adds input and output, jumps, comparisons and immediate parameters.
+    Day5,
+    /// Adds the relative base and relative parameters.
+    #[default]
+    Day9,
+}
+
+impl FeatureLevel {
+    /// The highest parameter mode digit allowed.
+    pub fn max_mode(self) -> i64 {
+        match self {
+            FeatureLevel::Day2 => 0,
+            FeatureLevel::Day5 => 1,
+            FeatureLevel::Day9 => 2,
+        }
+    }
+
+    /// The day of the puzzle the level is named after.
+    pub fn day(self) -> u32 {
+        match self {
+            FeatureLevel::Day2 => 2,
+            FeatureLevel::Day5 => 5,
+            FeatureLevel::Day9 => 9,
+        }
+    }
+
+    /// The level named after a puzzle's day, if there is one.
+    pub fn from_day(day: u32) -> Option<Self> {
+        match day {
+            2 => Some(FeatureLevel::Day2),
+            5 => Some(FeatureLevel::Day5),
+            9 => Some(FeatureLevel::Day9),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FeatureLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "day {}", self.day())
+    }
+}
+
+/// A table of instructions, by opcode, and the feature level that decides
+/// which parameter modes are allowed.
+#[derive(Clone, Default)]
+pub struct InstructionSet {
+    table: Vec<Option<Instruction>>,
+    level: FeatureLevel,
+}
+
+impl fmt::Debug for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InstructionSet")
+            .field("level", &self.level)
+            .field("instructions", &self.iter().map(|i| (i.code, i.mnemonic)).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl InstructionSet {
+    /// An empty instruction set, to add instructions to with `with`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The full instruction set, as of day 9.
+    pub fn standard() -> Self {
+        Self::new()
+            .with(Instruction::standard(Opcode::Add, 1, "ADD", 3, Some(2), add))
+            .with(Instruction::standard(Opcode::Multiply, 2, "MUL", 3, Some(2), multiply))
+            .with(Instruction::standard(Opcode::StoreInput, 3, "IN", 1, Some(0), input))
+            .with(Instruction::standard(Opcode::PushOutput, 4, "OUT", 1, None, output))
+            .with(Instruction::standard(Opcode::JumpIfTrue, 5, "JT", 2, None, jump_if_true).jumps_to(1))
+            .with(Instruction::standard(Opcode::JumpIfFalse, 6, "JF", 2, None, jump_if_false).jumps_to(1))
+            .with(Instruction::standard(Opcode::LessThan, 7, "LT", 3, Some(2), less_than))
+            .with(Instruction::standard(Opcode::Equals, 8, "EQ", 3, Some(2), equals))
+            .with(Instruction::standard(Opcode::UpdateBase, 9, "ARB", 1, None, update_base))
+            .with(Instruction::standard(Opcode::Halt, 99, "HLT", 0, None, halt))
+    }
+
+    /// Just the instructions and parameter modes from one of the puzzles, so
+    /// that anything newer is rejected.
+    pub fn for_level(level: FeatureLevel) -> Self {
+        let set = match level {
+            FeatureLevel::Day2 => Self::standard().only(&["ADD", "MUL", "HLT"]),
+            FeatureLevel::Day5 => Self::standard().without("ARB"),
+            FeatureLevel::Day9 => Self::standard(),
+        };
+        Self { level, ..set }
+    }
+
+    /// The instruction set from day 2.
+    pub fn day2() -> Self {
+        Self::for_level(FeatureLevel::Day2)
+    }
+
+    /// The instruction set from day 5, before the relative base came along.
+    pub fn day5() -> Self {
+        Self::for_level(FeatureLevel::Day5)
+    }
+
+    pub fn level(&self) -> FeatureLevel {
+        self.level
+    }
+
+    /// Adds an instruction, replacing any with the same code.
+    pub fn with(mut self, instruction: Instruction) -> Self {
+        let code = instruction.code as usize;
+        if code >= self.table.len() {
+            self.table.resize(OPCODES, None);
+        }
+        self.table[code] = Some(instruction);
+        self
+    }
+
+    /// Removes the instruction with a mnemonic, if there is one.
+    pub fn without(mut self, mnemonic: &str) -> Self {
+        for entry in &mut self.table {
+            if entry.is_some_and(|i| i.mnemonic.eq_ignore_ascii_case(mnemonic)) {
+                *entry = None;
+            }
+        }
+        self
+    }
+
+    /// Keeps just the instructions with the given mnemonics.
+    pub fn only(&self, mnemonics: &[&str]) -> Self {
+        let mut set = Self {
+            level: self.level,
+            ..Self::new()
+        };
+        for instruction in self.iter() {
+            if mnemonics.iter().any(|m| instruction.mnemonic.eq_ignore_ascii_case(m)) {
+                set = set.with(*instruction);
+            }
+        }
+        set
+    }
+
+    /// Looks up an instruction by its opcode (the last two digits only).
+    pub fn get(&self, code: i64) -> Option<&Instruction> {
+        if code < 0 {
+            return None;
+        }
+        self.table.get(code as usize)?.as_ref()
+    }
+
+    /// Looks up an instruction by its mnemonic, ignoring case.
+    pub fn by_mnemonic(&self, mnemonic: &str) -> Option<&Instruction> {
+        self.iter().find(|i| i.mnemonic.eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// The instructions, in order of opcode.
+    pub fn iter(&self) -> impl Iterator<Item = &Instruction> {
+        self.table.iter().flatten()
+    }
+}
+
+/// The standard instruction set, shared by every machine that doesn't ask for
+/// another.
+pub(super) fn standard_set() -> &'static Arc<InstructionSet> {
+    static STANDARD: OnceLock<Arc<InstructionSet>> = OnceLock::new();
+    STANDARD.get_or_init(|| Arc::new(InstructionSet::standard()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::cfg::{Exit, Target};
+    use crate::intcode::lint::{Diagnostic, Problem};
+    use crate::intcode::{Intcode, IntcodeError};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn sub(p: &[i64]) -> Option<Effect> {
+        p[0].checked_sub(p[1]).map(Effect::Store)
+    }
+
+    // Writes its second parameter to its first, so the write target isn't
+    // last.
+    fn mov(p: &[i64]) -> Option<Effect> {
+        Some(Effect::Store(p[1]))
+    }
+
+    fn dialect() -> InstructionSet {
+        InstructionSet::standard()
+            .with(Instruction::new(10, "SUB", 3, Some(2), sub))
+            .with(Instruction::new(11, "MOV", 2, Some(0), mov))
+    }
+
+    #[test]
+    fn subsets() {
+        init();
+        assert_eq!(vec!["ADD", "MUL", "HLT"], InstructionSet::day2().iter().map(|i| i.mnemonic).collect::<Vec<_>>());
+        assert!(InstructionSet::day5().by_mnemonic("arb").is_none());
+
+        let mut program = Intcode::from("1,0,0,0,99").unwrap();
+        program.set_instruction_set(InstructionSet::day2());
+        program.run().unwrap();
+
+        let mut program = Intcode::from("4,0,99").unwrap();
+        program.set_instruction_set(InstructionSet::day2());
+        let level = FeatureLevel::Day2;
+        assert_eq!(Err(IntcodeError::UnsupportedOpcode { pc: 0, opcode: 4, level }), program.run());
+        assert_eq!(
+            Err(crate::intcode::asm::AsmError {
+                line: 1,
+                message: "unknown mnemonic \"out\"".to_string()
+            }),
+            InstructionSet::day2().assemble("out #1")
+        );
+    }
+
+    #[test]
+    fn feature_levels() {
+        init();
+        let run = |source: &str, level| {
+            let mut program = Intcode::from(source).unwrap();
+            program.set_instruction_set(InstructionSet::for_level(level));
+            program.run()
+        };
+        let day5 = FeatureLevel::Day5;
+
+        // Immediate parameters came with day 5, relative ones with day 9.
+        assert_eq!(
+            Err(IntcodeError::UnsupportedParamMode { pc: 0, opcode: 1002, mode: 1, level: FeatureLevel::Day2 }),
+            run("1002,4,3,4,33", FeatureLevel::Day2)
+        );
+        assert_eq!(Ok(()), run("1002,4,3,4,33", day5));
+        assert_eq!(
+            Err(IntcodeError::UnsupportedParamMode { pc: 0, opcode: 201, mode: 2, level: day5 }),
+            run("201,0,0,0,99", day5)
+        );
+        assert_eq!(
+            Err(IntcodeError::UnsupportedOpcode { pc: 0, opcode: 109, level: day5 }),
+            run("109,1,99", day5)
+        );
+        assert_eq!(Ok(()), run("109,1,99", FeatureLevel::Day9));
+
+        // Opcodes no puzzle used are just unknown.
+        assert_eq!(Err(IntcodeError::UnknownOpcode { pc: 0, opcode: 42 }), run("42", day5));
+        assert_eq!(
+            "opcode 109 at pc 0 isn't supported at the day 5 feature level",
+            run("109,1,99", day5).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn custom_instructions() {
+        init();
+        let dialect = dialect();
+        let source = "
+                    sub #10, #3 -> [x]
+                    mov #5 -> [y]
+                    out [x]
+                    out [y]
+                    hlt
+            x:      db 0
+            y:      db 0
+        ";
+        let program = dialect.assemble(source).unwrap();
+        assert_eq!("1110,10,3,12,1011,13,5,4,12,4,13,99,0,0", program);
+
+        let mut machine = Intcode::from(&program).unwrap();
+        machine.set_instruction_set(dialect.clone());
+        machine.run().unwrap();
+        assert_eq!(vec![7, 5], machine.output);
+
+        // The standard set doesn't know these.
+        let mut machine = Intcode::from(&program).unwrap();
+        assert_eq!(Err(IntcodeError::UnknownOpcode { pc: 0, opcode: 1110 }), machine.run());
+
+        let cells: Vec<i64> = program.split(',').map(|c| c.parse().unwrap()).collect();
+        let listing: Vec<String> = dialect.disassemble(&cells).iter().map(|l| l.to_string()).collect();
+        assert_eq!("0000: SUB #10, #3 -> [12]", listing[0]);
+        assert_eq!("0004: MOV #5 -> [13]", listing[1]);
+        assert_eq!(Ok(program), dialect.assemble(&listing.join("\n")));
+    }
+
+    #[test]
+    fn stores_without_a_write_parameter() {
+        init();
+        // Both return effects that need somewhere to write, but don't say where.
+        let dialect = InstructionSet::standard()
+            .with(Instruction::new(13, "BAD", 1, None, |p| Some(Effect::Store(p[0]))))
+            .with(Instruction::new(14, "GET", 0, None, |_| Some(Effect::Input)));
+        let run = |source: &str| {
+            let mut machine = Intcode::from(source).unwrap();
+            machine.set_instruction_set(dialect.clone());
+            machine.input.push_back(1);
+            machine.run()
+        };
+        assert_eq!(Err(IntcodeError::NoWriteParameter { pc: 2, opcode: 13 }), run("4,0,1113,5,99"));
+        assert_eq!(Err(IntcodeError::NoWriteParameter { pc: 0, opcode: 14 }), run("14,99"));
+        assert_eq!("13 at pc 2 has nowhere to write its result", run("4,0,1113,5,99").unwrap_err().to_string());
+    }
+
+    fn jmp(p: &[i64]) -> Option<Effect> {
+        Some(Effect::Jump(p[0]))
+    }
+
+    #[test]
+    fn dialect_jumps() {
+        init();
+        let dialect = InstructionSet::standard().with(Instruction::new(12, "JMP", 1, None, jmp).jumps_to(0));
+
+        // Jumps over some data to the halt.
+        let program = dialect.assemble("jmp #end\ndb 42\nend: hlt").unwrap();
+        let cells: Vec<i64> = program.split(',').map(|c| c.parse().unwrap()).collect();
+        let cfg = dialect.cfg(&cells);
+        assert_eq!(vec![0, 3], cfg.blocks.keys().copied().collect::<Vec<_>>());
+        assert_eq!(Exit::Jump(Target::Static(3)), cfg.blocks[&0].exit);
+        assert_eq!(Vec::<Diagnostic>::new(), dialect.lint(&cells));
+
+        assert_eq!(
+            vec![Diagnostic {
+                address: 0,
+                problem: Problem::JumpOutside { target: 50 }
+            }],
+            dialect.lint(&[1112, 50, 99])
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Stopping runs that would otherwise go on forever: instruction budgets,
+//! deadlines, and spotting a machine going round the same loop.
+
+use super::{Effect, InputSource, Intcode, IntcodeError, Memory, OutputSink, StepResult};
+use std::time::{Duration, Instant};
+
+/// How many instructions to run between looks at the clock, which is much
+/// slower than a step.
+const CLOCK_INTERVAL: u64 = 4096;
+
+impl<I: InputSource, O: OutputSink, M: Memory> Intcode<I, O, M> {
+    /// Takes a step as `run` would, returning whether the program halted.
+    fn run_step(&mut self) -> Result<bool, IntcodeError> {
+        match self.step()? {
+            StepResult::Halt => Ok(true),
+            StepResult::NeedInput => Err(IntcodeError::InputExhausted { pc: self.pc }),
+            _ => Ok(false),
+        }
+    }
+
+    /// Like `run`, but gives up with StepLimit if the program hasn't halted
+    /// within `max_steps` instructions (counting the halt).
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Result<(), IntcodeError> {
+        for _ in 0..max_steps {
+            if self.run_step()? {
+                return Ok(());
+            }
+        }
+        Err(IntcodeError::StepLimit { pc: self.pc, steps: max_steps })
+    }
+
+    /// Like `run`, but gives up with Timeout if the program hasn't halted by
+    /// `deadline`. The clock is only checked every few thousand instructions.
+    pub fn run_until_deadline(&mut self, deadline: Instant) -> Result<(), IntcodeError> {
+        let mut steps = 0;
+        loop {
+            if steps % CLOCK_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(IntcodeError::Timeout { pc: self.pc, steps });
+            }
+            if self.run_step()? {
+                return Ok(());
+            }
+            steps += 1;
+        }
+    }
+
+    /// Like `run`, but gives up with Timeout if the program hasn't halted
+    /// within `timeout`.
+    pub fn run_with_timeout(&mut self, timeout: Duration) -> Result<(), IntcodeError> {
+        self.run_until_deadline(Instant::now() + timeout)
+    }
+}
+
+impl<I: InputSource, O: OutputSink, M: Memory + Clone + PartialEq> Intcode<I, O, M> {
+    /// Like `run`, but gives up with InfiniteLoop if the machine gets back to
+    /// exactly a state it was in before (pc, relative base and all of memory)
+    /// without any input or output in between. Such a program can never halt.
+    ///
+    /// Only exact repeats are caught: a loop that counts upwards forever
+    /// never repeats, so use a limit or timeout as well for those.
+    pub fn run_detecting_loops(&mut self) -> Result<(), IntcodeError> {
+        // Brent's algorithm: keep the state from the last power-of-two step,
+        // so any cycle is found within a couple of laps without storing more
+        // than one copy of memory.
+        let mut saved = (self.pc, self.relative_base, self.program.clone());
+        let mut power = 1;
+        let mut length = 0;
+
+        loop {
+            let op = self.decode_at(self.pc)?;
+            let params = op.get_params(self, self.pc, self.relative_base)?;
+            let io = matches!((op.execute)(&params[..op.num_params as usize]), Some(Effect::Input | Effect::Output(_)));
+            if self.run_step()? {
+                return Ok(());
+            }
+            length += 1;
+
+            // Input and output change what's outside the machine, so nothing
+            // before them counts as the same state.
+            if !io && self.pc == saved.0 && self.relative_base == saved.1 && self.program == saved.2 {
+                return Err(IntcodeError::InfiniteLoop { pc: self.pc, period: length });
+            }
+            if io || length == power {
+                saved = (self.pc, self.relative_base, self.program.clone());
+                power = if io { 1 } else { power * 2 };
+                length = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // Jumps back to itself forever.
+    const SPIN: &str = "1105,1,0";
+
+    // Flips address 20 between 0 and 1 forever, so repeats every four steps.
+    const TOGGLE: &str = "1008,20,0,20,1105,1,0";
+
+    // Counts down from 1000 without any output, then halts.
+    const COUNTDOWN: &str = "1101,1000,0,20,1001,20,-1,20,1005,20,4,99";
+
+    #[test]
+    fn step_limit() {
+        init();
+        let mut program = Intcode::from(SPIN).unwrap();
+        assert_eq!(Err(IntcodeError::StepLimit { pc: 0, steps: 10 }), program.run_with_limit(10));
+
+        let mut program = Intcode::from("1101,1,1,0,99").unwrap();
+        assert_eq!(Err(IntcodeError::StepLimit { pc: 4, steps: 1 }), program.run_with_limit(1));
+        assert_eq!(Ok(()), program.run_with_limit(1));
+        assert_eq!(Ok(2), program.mem_get(0));
+    }
+
+    #[test]
+    fn timeout() {
+        init();
+        let mut program = Intcode::from(SPIN).unwrap();
+        match program.run_with_timeout(Duration::from_millis(10)) {
+            Err(IntcodeError::Timeout { pc: 0, steps }) => assert!(steps > 0),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        let mut program = Intcode::from(COUNTDOWN).unwrap();
+        assert_eq!(Ok(()), program.run_with_timeout(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn detects_loops() {
+        init();
+        let mut program = Intcode::from(SPIN).unwrap();
+        assert_eq!(Err(IntcodeError::InfiniteLoop { pc: 0, period: 1 }), program.run_detecting_loops());
+        let mut program = Intcode::from(TOGGLE).unwrap();
+        assert_eq!(Err(IntcodeError::InfiniteLoop { pc: 4, period: 4 }), program.run_detecting_loops());
+        let mut program = Intcode::from(COUNTDOWN).unwrap();
+        assert_eq!(Ok(()), program.run_detecting_loops());
+    }
+
+    #[test]
+    fn input_is_not_a_loop() {
+        init();
+        // Reads the same value over and over, which leaves the machine in the
+        // same state each time round.
+        let mut program = Intcode::from("3,20,1105,1,0").unwrap();
+        program.input.extend(vec![5, 5, 5]);
+        assert_eq!(Err(IntcodeError::InputExhausted { pc: 0 }), program.run_detecting_loops());
+    }
+}
@@ -0,0 +1,294 @@
+//! Checking a program for likely mistakes before running it.
+//!
+//! Only code reachable from address zero by jumps whose targets are known
+//! statically is checked (the same code `cfg` finds), so anything reached only
+//! through an indirect jump is missed. Likewise only position-mode reads and
+//! writes can be checked, as relative ones depend on the relative base.
+
+use super::cfg::{Cfg, Exit};
+use super::isa::{standard_set, InstructionSet};
+use super::{IntcodeError, Operation, ParamMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// How bad a problem is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The program will fail if this is ever reached.
+    Error,
+    /// Probably a mistake, but some programs do it on purpose.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Something wrong with an instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The instruction doesn't decode.
+    Undecodable(IntcodeError),
+    /// The instruction doesn't decode as it stands, but something writes to
+    /// it, so it may be patched before it runs.
+    Patched(IntcodeError),
+    /// The instruction writes to an immediate-mode parameter.
+    ImmediateWrite,
+    /// Execution carries on past the end of the image, or into an instruction
+    /// cut short by it.
+    RunsOffEnd,
+    /// A jump to somewhere outside the image.
+    JumpOutside { target: i64 },
+    /// A read or write of a negative address.
+    NegativeAddress { address: i64 },
+    /// A read beyond the end of the image, where nothing is ever written, so
+    /// it always reads zero.
+    UninitialisedRead { address: i64 },
+    /// A write into the cells of the instruction at `instruction`, which can
+    /// run after the write.
+    WriteIntoCode { address: i64, instruction: usize },
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::Patched(_) | Problem::UninitialisedRead { .. } | Problem::WriteIntoCode { .. } => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Undecodable(e) => write!(f, "{}", e),
+            Problem::Patched(e) => write!(f, "{}, unless patched first", e),
+            Problem::ImmediateWrite => write!(f, "writes to an immediate-mode parameter"),
+            Problem::RunsOffEnd => write!(f, "runs off the end of the program"),
+            Problem::JumpOutside { target } => write!(f, "jumps to {}, outside the program", target),
+            Problem::NegativeAddress { address } => write!(f, "uses negative address {}", address),
+            Problem::UninitialisedRead { address } => {
+                write!(f, "reads {}, past the end of the program and never written", address)
+            }
+            Problem::WriteIntoCode { address, instruction } => {
+                write!(f, "writes to {}, part of the instruction at {}", address, instruction)
+            }
+        }
+    }
+}
+
+/// A problem, and the address of the instruction it was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub address: usize,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: {}: {}", self.address, self.problem.severity(), self.problem)
+    }
+}
+
+/// The blocks that can run after leaving each block.
+fn reachable_after(cfg: &Cfg) -> BTreeMap<usize, BTreeSet<usize>> {
+    cfg.blocks
+        .iter()
+        .map(|(&start, block)| {
+            let mut seen = BTreeSet::new();
+            let mut pending = block.successors();
+            while let Some(next) = pending.pop() {
+                if seen.insert(next) {
+                    pending.extend(cfg.blocks.get(&next).map(|b| b.successors()).unwrap_or_default());
+                }
+            }
+            (start, seen)
+        })
+        .collect()
+}
+
+/// Checks a program for problems, returning them in order of address.
+pub fn lint(program: &[i64]) -> Vec<Diagnostic> {
+    standard_set().lint(program)
+}
+
+impl InstructionSet {
+    /// Checks a program written for this instruction set, in the same way as
+    /// `lint`.
+    pub fn lint(&self, program: &[i64]) -> Vec<Diagnostic> {
+        let cfg = self.cfg(program);
+        let after = reachable_after(&cfg);
+        let mut diagnostics = Vec::new();
+
+        // Gather up the reachable instructions, the block each is in and the
+        // cells each covers.
+        let mut instructions = Vec::new();
+        let mut code = BTreeMap::new();
+        for block in cfg.blocks.values() {
+            for line in &block.lines {
+                if let Some((op, params)) = Operation::decode_in(program, line.address, self) {
+                    code.extend((line.address..line.address + line.cells.len()).map(|cell| (cell, line.address)));
+                    instructions.push((line.address, block.start, op, params));
+                }
+            }
+        }
+        let block_of: BTreeMap<usize, usize> =
+            instructions.iter().map(|&(address, block, ..)| (address, block)).collect();
+
+        // Addresses written to directly, which count as initialised when read.
+        let written: BTreeSet<i64> = instructions
+            .iter()
+            .filter_map(|(_, _, op, params)| {
+                let ii = op.param_modes().iter().position(|&mode| mode == ParamMode::Reference)?;
+                Some(params[ii])
+            })
+            .collect();
+
+        // Blocks stop early where something doesn't decode.
+        for block in cfg.blocks.values().filter(|block| block.exit == Exit::Invalid) {
+            let problem = match program.get(block.end) {
+                None => Problem::RunsOffEnd,
+                Some(&cell) => match Operation::decode_with(cell, block.end as i64, self) {
+                    Err(e) if written.contains(&(block.end as i64)) => Problem::Patched(e),
+                    Err(IntcodeError::WriteInImmediateMode { .. }) => Problem::ImmediateWrite,
+                    Err(e) => Problem::Undecodable(e),
+                    // It decodes, but the end of the program cuts it short.
+                    Ok(_) => Problem::RunsOffEnd,
+                },
+            };
+            diagnostics.push(Diagnostic {
+                address: block.end,
+                problem,
+            });
+        }
+
+        let len = program.len() as i64;
+        for (address, block, op, params) in instructions {
+            let mut report = |problem| diagnostics.push(Diagnostic { address, problem });
+
+            if let Some(jump) = op.jump(params, self) {
+                let never = jump.taken == Some(false);
+                if !never && jump.mode == ParamMode::Immediate && !(0..len).contains(&jump.target) {
+                    report(Problem::JumpOutside { target: jump.target });
+                }
+            }
+
+            for (&mode, &value) in op.param_modes().iter().zip(params) {
+                match mode {
+                    ParamMode::Position | ParamMode::Reference if value < 0 => {
+                        report(Problem::NegativeAddress { address: value })
+                    }
+                    ParamMode::Position if value >= len && !written.contains(&value) => {
+                        report(Problem::UninitialisedRead { address: value })
+                    }
+                    ParamMode::Reference => {
+                        // Writing over code that has already run, and won't again,
+                        // is harmless; day 2 programs do it all the time.
+                        let instruction = match code.get(&(value as usize)) {
+                            Some(&instruction) => instruction,
+                            None => continue,
+                        };
+                        let target = block_of[&instruction];
+                        if (target == block && instruction > address) || after[&block].contains(&target) {
+                            report(Problem::WriteIntoCode {
+                                address: value,
+                                instruction,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        diagnostics.sort_by_key(|d| d.address);
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Intcode, Memory};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn lint_source(source: &str) -> Vec<Diagnostic> {
+        lint(&Intcode::from(source).unwrap().program.to_vec())
+    }
+
+    #[test]
+    fn clean_programs() {
+        init();
+        assert_eq!(Vec::<Diagnostic>::new(), lint_source("1,0,0,0,99"));
+        // Written before it's read, so it's fine.
+        assert_eq!(Vec::<Diagnostic>::new(), lint_source("1101,1,1,100,4,100,99"));
+        // The 42 is never reached.
+        assert_eq!(Vec::<Diagnostic>::new(), lint_source("1105,1,4,42,99"));
+    }
+
+    #[test]
+    fn undecodable() {
+        init();
+        assert_eq!(
+            vec![Diagnostic {
+                address: 4,
+                problem: Problem::Undecodable(IntcodeError::UnknownOpcode { pc: 4, opcode: 42 })
+            }],
+            lint_source("1,0,0,0,42")
+        );
+        assert_eq!(
+            vec![Diagnostic {
+                address: 0,
+                problem: Problem::ImmediateWrite
+            }],
+            lint_source("11101,1,1,5,99")
+        );
+        assert_eq!(
+            vec![Diagnostic {
+                address: 4,
+                problem: Problem::RunsOffEnd
+            }],
+            lint_source("1,0,0,0,1,0")
+        );
+        // Patched into an ADD before it's reached, as the day 5 input does.
+        assert_eq!(
+            vec![Diagnostic {
+                address: 4,
+                problem: Problem::Patched(IntcodeError::UnknownOpcode { pc: 4, opcode: 1100 })
+            }],
+            lint_source("1101,1,0,4,1100,0,0,0,99")
+        );
+    }
+
+    #[test]
+    fn memory_and_jumps() {
+        init();
+        let diagnostics = lint_source("1,100,0,6,1105,1,50,4,-1");
+        let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            vec![
+                "0000: warning: reads 100, past the end of the program and never written",
+                "0000: warning: writes to 6, part of the instruction at 4",
+                "0004: error: jumps to 50, outside the program",
+            ],
+            lines
+        );
+        assert_eq!(
+            vec![Diagnostic {
+                address: 0,
+                problem: Problem::NegativeAddress { address: -1 }
+            }],
+            lint_source("4,-1,99")
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
+/// Storage for an Intcode program's memory. Cells that have never been
+/// written read as zero.
+pub trait Memory {
+    fn load(&self, address: usize) -> i64;
+    fn store(&mut self, address: usize, value: i64);
+
+    /// One past the highest address that has ever held a value.
+    fn extent(&self) -> usize;
+
+    /// Copies out memory from address zero up to the extent.
+    fn to_vec(&self) -> Vec<i64> {
+        (0..self.extent()).map(|address| self.load(address)).collect()
+    }
+}
+
+/// Plain dense memory, which grows to fit whatever is written to it. Fast,
+/// but one write to a huge address allocates everything below it.
+impl Memory for Vec<i64> {
+    fn load(&self, address: usize) -> i64 {
+        *self.as_slice().get(address).unwrap_or(&0)
+    }
+
+    fn store(&mut self, address: usize, value: i64) {
+        if address >= self.len() {
+            self.resize(address + 1, 0);
+        }
+        self[address] = value;
+    }
+
+    fn extent(&self) -> usize {
+        self.len()
+    }
+}
+
+const PAGE_SIZE: usize = 1024;
+
+/// Memory that keeps the program image in a dense Vec, and anything written
+/// beyond it in pages which are only allocated when first written.
+///
+/// Cells are i64s unless another type is given, as `WideIntcode` does. Cells
+/// that have never been written hold the type's default, which is zero.
+#[derive(Debug, Clone, Default)]
+pub struct PagedMemory<C = i64> {
+    image: Vec<C>,
+    pages: HashMap<usize, Box<[C]>>,
+    extent: usize,
+}
+
+impl<C: Clone + Default> PagedMemory<C> {
+    /// Finds the cell for an address beyond the image, allocating its page if
+    /// needed.
+    fn cell_mut(&mut self, address: usize) -> &mut C {
+        self.extent = self.extent.max(address + 1);
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| vec![C::default(); PAGE_SIZE].into_boxed_slice());
+        &mut page[address % PAGE_SIZE]
+    }
+
+    /// The program image, which is always held densely.
+    pub fn image(&self) -> &[C] {
+        &self.image
+    }
+
+    /// The cell at an address, or None if nothing has been allocated there.
+    pub fn get(&self, address: usize) -> Option<&C> {
+        if address < self.image.len() {
+            return Some(&self.image[address]);
+        }
+        self.pages.get(&(address / PAGE_SIZE)).map(|page| &page[address % PAGE_SIZE])
+    }
+
+    /// The cell at an address, allocating its page if needed.
+    pub fn get_mut(&mut self, address: usize) -> &mut C {
+        if address < self.image.len() {
+            &mut self.image[address]
+        } else {
+            self.cell_mut(address)
+        }
+    }
+
+    /// Raises the extent to at least `extent`, as a write of zero there would.
+    pub(super) fn extend_to(&mut self, extent: usize) {
+        self.extent = self.extent.max(extent);
+    }
+}
+
+impl PagedMemory {
+
+    /// Runs of consecutive non-zero cells beyond the image, in order of
+    /// address, each as its start address and values.
+    pub fn runs(&self) -> Vec<(usize, Vec<i64>)> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_by_key(|&(&page, _)| page);
+
+        let mut runs: Vec<(usize, Vec<i64>)> = Vec::new();
+        for (&page, cells) in pages {
+            for (offset, &value) in cells.iter().enumerate().filter(|&(_, &value)| value != 0) {
+                let address = page * PAGE_SIZE + offset;
+                match runs.last_mut() {
+                    Some((start, values)) if *start + values.len() == address => values.push(value),
+                    _ => runs.push((address, vec![value])),
+                }
+            }
+        }
+        runs
+    }
+}
+
+impl<C> From<Vec<C>> for PagedMemory<C> {
+    fn from(image: Vec<C>) -> Self {
+        Self {
+            extent: image.len(),
+            image,
+            pages: HashMap::new(),
+        }
+    }
+}
+
+impl Memory for PagedMemory {
+    fn load(&self, address: usize) -> i64 {
+        self[address]
+    }
+
+    fn store(&mut self, address: usize, value: i64) {
+        self[address] = value;
+    }
+
+    fn extent(&self) -> usize {
+        self.extent
+    }
+}
+
+impl Index<usize> for PagedMemory {
+    type Output = i64;
+
+    fn index(&self, address: usize) -> &i64 {
+        self.get(address).unwrap_or(&0)
+    }
+}
+
+impl IndexMut<usize> for PagedMemory {
+    fn index_mut(&mut self, address: usize) -> &mut i64 {
+        self.get_mut(address)
+    }
+}
+
+/// Compares contents without filling in the gaps, as the extent can be huge.
+impl PartialEq for PagedMemory {
+    fn eq(&self, other: &Self) -> bool {
+        let image = 0..self.image.len().max(other.image.len());
+        let pages = self.pages.keys().chain(other.pages.keys());
+        let mut addresses = image.chain(pages.flat_map(|page| page * PAGE_SIZE..(page + 1) * PAGE_SIZE));
+
+        self.extent == other.extent && addresses.all(|address| self[address] == other[address])
+    }
+}
+
+impl PartialEq<Vec<i64>> for PagedMemory {
+    fn eq(&self, other: &Vec<i64>) -> bool {
+        self.to_vec() == *other
+    }
+}
+
+impl PartialEq<PagedMemory> for Vec<i64> {
+    fn eq(&self, other: &PagedMemory) -> bool {
+        *self == other.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paged_memory() {
+        let mut memory = PagedMemory::from(vec![1, 2, 3]);
+        memory.store(1, 5);
+        memory.store(1_000_000_000_000, 7);
+        assert_eq!(1, memory.pages.len());
+        assert_eq!(5, memory.load(1));
+        assert_eq!(7, memory.load(1_000_000_000_000));
+        assert_eq!(0, memory.load(999_999_999_999));
+        assert_eq!(0, memory.load(5));
+        assert_eq!(1_000_000_000_001, memory.extent());
+    }
+
+    #[test]
+    fn runs() {
+        let mut memory = PagedMemory::from(vec![1, 2, 3]);
+        memory.store(1023, 4);
+        memory.store(1024, 5);
+        memory.store(1026, 6);
+        memory.store(5000, 0);
+        assert_eq!(vec![(1023, vec![4, 5]), (1026, vec![6])], memory.runs());
+        assert_eq!(5001, memory.extent());
+    }
+
+    #[test]
+    fn dense_memory() {
+        let mut memory = vec![1, 2, 3];
+        memory.store(5, 9);
+        assert_eq!(vec![1, 2, 3, 0, 0, 9], memory);
+        assert_eq!(0, memory.load(100));
+    }
+
+    #[test]
+    fn compare_huge() {
+        let mut a = PagedMemory::from(vec![1, 2]);
+        let mut b = PagedMemory::from(vec![1, 2]);
+        a.store(1_000_000_000_000, 7);
+        assert!(a != b);
+        b.store(1_000_000_000_000, 7);
+        assert!(a == b);
+        b.store(5, 0);
+        assert!(a == b);
+        b.store(2_000_000_000_000, 0);
+        assert!(a != b);
+    }
+
+    #[test]
+    fn compare_with_vec() {
+        let mut memory = PagedMemory::from(vec![1, 2]);
+        memory[3] = 4;
+        assert_eq!(vec![1, 2, 0, 4], memory);
+    }
+}
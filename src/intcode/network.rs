@@ -0,0 +1,256 @@
+//! Networks of Intcode machines, passing their output to each other.
+
+use super::{Intcode, IntcodeError, StepResult};
+
+/// How machines in a network are connected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topology {
+    /// Each machine's output goes to the next machine, and the last
+    /// machine's output goes back round to the first.
+    Ring,
+    /// Each machine's output goes to the next machine, and the last
+    /// machine's output leaves the network.
+    Chain,
+    /// Machines output packets of three values `(dest, x, y)`, and `x` and
+    /// `y` are delivered to machine `dest`. Packets for addresses outside
+    /// the network are kept in `undelivered`.
+    ///
+    /// If `idle_input` is set, a machine waiting on an empty queue is given
+    /// that value rather than being left blocked.
+    Bus { idle_input: Option<i64> },
+}
+
+/// Why a network stopped running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkState {
+    /// Every machine has halted.
+    Halted,
+    /// A whole round passed in which no machine got any input or produced
+    /// any output, and every machine still running is waiting for input. For
+    /// a ring or chain this is a deadlock; for a bus it's idling.
+    Idle,
+}
+
+/// An error in one of the machines in a network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineError {
+    pub machine: usize,
+    pub error: IntcodeError,
+}
+
+#[derive(Debug, Clone)]
+pub struct Network {
+    pub machines: Vec<Intcode>,
+    topology: Topology,
+    halted: Vec<bool>,
+    /// Partial packets output by each machine on a bus.
+    partial: Vec<Vec<i64>>,
+    /// The last value output by each machine.
+    pub last_output: Vec<Option<i64>>,
+    /// Values that came out of the end of a chain.
+    pub output: Vec<i64>,
+    /// Packets sent on a bus to addresses outside the network.
+    pub undelivered: Vec<(i64, i64, i64)>,
+}
+
+impl Network {
+    pub fn new(machines: Vec<Intcode>, topology: Topology) -> Self {
+        let n = machines.len();
+        Self {
+            machines,
+            topology,
+            halted: vec![false; n],
+            partial: vec![Vec::new(); n],
+            last_output: vec![None; n],
+            output: Vec::new(),
+            undelivered: Vec::new(),
+        }
+    }
+
+    /// Builds a network of `n` copies of a machine.
+    pub fn copies(machine: &Intcode, n: usize, topology: Topology) -> Self {
+        Self::new(vec![machine.clone(); n], topology)
+    }
+
+    /// Queues up input for a machine.
+    pub fn send(&mut self, machine: usize, value: i64) {
+        self.machines[machine].input.push_back(value);
+    }
+
+    pub fn is_halted(&self, machine: usize) -> bool {
+        self.halted[machine]
+    }
+
+    /// Passes output from machine `from` on to wherever it's going.
+    fn route(&mut self, from: usize, values: Vec<i64>) {
+        let n = self.machines.len();
+        match self.topology {
+            Topology::Ring => values.into_iter().for_each(|v| self.send((from + 1) % n, v)),
+            Topology::Chain if from + 1 == n => self.output.extend(values),
+            Topology::Chain => values.into_iter().for_each(|v| self.send(from + 1, v)),
+            Topology::Bus { .. } => {
+                self.partial[from].extend(values);
+                let complete = self.partial[from].len() / 3 * 3;
+                let packets: Vec<i64> = self.partial[from].drain(..complete).collect();
+                for packet in packets.chunks(3) {
+                    let (dest, x, y) = (packet[0], packet[1], packet[2]);
+                    debug!("Packet {} -> {}: ({}, {})", from, dest, x, y);
+                    if dest >= 0 && (dest as usize) < n {
+                        self.send(dest as usize, x);
+                        self.send(dest as usize, y);
+                    } else {
+                        self.undelivered.push((dest, x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gives each machine that hasn't halted a turn, running it until it
+    /// needs input or halts, and routes its output. Returns whether anything
+    /// happened this round.
+    pub fn run_round(&mut self) -> Result<bool, MachineError> {
+        let mut active = false;
+
+        for ii in 0..self.machines.len() {
+            if self.halted[ii] {
+                continue;
+            }
+
+            let machine = &mut self.machines[ii];
+            if machine.input.is_empty() {
+                if let Topology::Bus { idle_input: Some(value) } = self.topology {
+                    machine.input.push_back(value);
+                }
+            } else {
+                active = true;
+            }
+
+            loop {
+                let result = machine
+                    .run_until_input_or_halt()
+                    .map_err(|error| MachineError { machine: ii, error })?;
+                match result {
+                    StepResult::Halt => {
+                        self.halted[ii] = true;
+                        active = true;
+                        break;
+                    }
+                    StepResult::NeedInput => break,
+                    _ => {}
+                }
+            }
+
+            let values: Vec<i64> = machine.output.drain(..).collect();
+            if let Some(&last) = values.last() {
+                self.last_output[ii] = Some(last);
+                active = true;
+            }
+            self.route(ii, values);
+        }
+
+        Ok(active)
+    }
+
+    /// Runs rounds until every machine halts or the network goes idle.
+    pub fn run(&mut self) -> Result<NetworkState, MachineError> {
+        loop {
+            let active = self.run_round()?;
+            if self.halted.iter().all(|&halted| halted) {
+                return Ok(NetworkState::Halted);
+            }
+            if !active {
+                return Ok(NetworkState::Idle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn machine(source: &str) -> Intcode {
+        Intcode::from(&assemble(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn ring_of_amplifiers() {
+        init();
+        let program = Intcode::from(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        )
+        .unwrap();
+        let mut network = Network::copies(&program, 5, Topology::Ring);
+        for (ii, &phase) in [9, 8, 7, 6, 5].iter().enumerate() {
+            network.send(ii, phase);
+        }
+        network.send(0, 0);
+
+        assert_eq!(Ok(NetworkState::Halted), network.run());
+        assert_eq!(Some(139629729), network.last_output[4]);
+    }
+
+    #[test]
+    fn chain_of_amplifiers() {
+        init();
+        let program = Intcode::from("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0").unwrap();
+        let mut network = Network::copies(&program, 5, Topology::Chain);
+        for (ii, &phase) in [4, 3, 2, 1, 0].iter().enumerate() {
+            network.send(ii, phase);
+        }
+        network.send(0, 0);
+
+        assert_eq!(Ok(NetworkState::Halted), network.run());
+        assert_eq!(vec![43210], network.output);
+    }
+
+    #[test]
+    fn bus_packets() {
+        init();
+        let sender = machine("out #1\nout #5\nout #6\nhlt");
+        let adder = machine(
+            "in -> [x]\nin -> [y]\nadd [x], [y] -> [x]\nout #255\nout #0\nout [x]\nhlt\nx: db 0\ny: db 0",
+        );
+        let mut network = Network::new(vec![sender, adder], Topology::Bus { idle_input: None });
+
+        assert_eq!(Ok(NetworkState::Halted), network.run());
+        assert_eq!(vec![(255, 0, 11)], network.undelivered);
+    }
+
+    #[test]
+    fn idle_and_deadlock() {
+        init();
+        let poller = machine("loop: in -> [100]\njt #1, #loop");
+        let mut network = Network::copies(&poller, 3, Topology::Bus { idle_input: Some(-1) });
+        assert_eq!(Ok(NetworkState::Idle), network.run());
+
+        // Give one a packet, and it's active again for a round.
+        network.send(1, 7);
+        assert_eq!(Ok(true), network.run_round());
+        assert_eq!(Ok(false), network.run_round());
+
+        let waiter = machine("in -> [100]\nhlt");
+        let mut network = Network::copies(&waiter, 2, Topology::Ring);
+        assert_eq!(Ok(NetworkState::Idle), network.run());
+    }
+
+    #[test]
+    fn reports_failing_machine() {
+        init();
+        let machines = vec![machine("hlt"), machine("db 42")];
+        let mut network = Network::new(machines, Topology::Chain);
+        assert_eq!(
+            Err(MachineError {
+                machine: 1,
+                error: IntcodeError::UnknownOpcode { pc: 0, opcode: 42 }
+            }),
+            network.run()
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Profiling where a program spends its time.
+
+use super::{InstructionSet, Intcode};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Counts gathered while a program runs with profiling on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Instructions executed.
+    pub instructions: u64,
+    /// Executions of each instruction, by address.
+    pub by_pc: BTreeMap<i64, u64>,
+    /// Executions of each kind of instruction, by mnemonic.
+    pub by_opcode: BTreeMap<&'static str, u64>,
+    /// Instructions executed up to and including each output, since the one
+    /// before.
+    pub output_intervals: Vec<u64>,
+    since_output: u64,
+    /// The highest address read, written or executed.
+    pub max_address: i64,
+    /// The lowest and highest the relative base has been.
+    pub relative_base_range: (i64, i64),
+}
+
+impl Profile {
+    fn new(relative_base: i64) -> Self {
+        Self {
+            instructions: 0,
+            by_pc: BTreeMap::new(),
+            by_opcode: BTreeMap::new(),
+            output_intervals: Vec::new(),
+            since_output: 0,
+            max_address: 0,
+            relative_base_range: (relative_base, relative_base),
+        }
+    }
+
+    /// Counts an instruction that has just been executed, and whether it
+    /// output anything. `touched` is the highest address it used.
+    pub(super) fn record(
+        &mut self,
+        pc: i64,
+        mnemonic: &'static str,
+        output: bool,
+        touched: i64,
+        relative_base: i64,
+    ) {
+        self.instructions += 1;
+        *self.by_pc.entry(pc).or_insert(0) += 1;
+        *self.by_opcode.entry(mnemonic).or_insert(0) += 1;
+
+        self.since_output += 1;
+        if output {
+            self.output_intervals.push(self.since_output);
+            self.since_output = 0;
+        }
+
+        self.max_address = self.max_address.max(touched);
+        let (low, high) = self.relative_base_range;
+        self.relative_base_range = (low.min(relative_base), high.max(relative_base));
+    }
+
+    /// Formats a count along with its share of all instructions executed.
+    fn share(&self, count: u64) -> String {
+        let percent = 100.0 * count as f64 / self.instructions.max(1) as f64;
+        format!("{:>12} {:>6.2}%", count, percent)
+    }
+
+    /// Builds a report of where the time went, with the hottest basic blocks
+    /// and instructions of `program` (as it was before running) listed first.
+    /// At most `top` of each are shown. `isa` should be the instruction set
+    /// the program ran with.
+    pub fn report(&self, isa: &InstructionSet, program: &[i64], top: usize) -> String {
+        let mut report = String::new();
+
+        writeln!(report, "Instructions executed: {}", self.instructions).unwrap();
+        writeln!(report, "Highest address used:  {}", self.max_address).unwrap();
+        let (low, high) = self.relative_base_range;
+        writeln!(report, "Relative base range:   {}..={}", low, high).unwrap();
+        match self.output_intervals.iter().max() {
+            Some(longest) => writeln!(
+                report,
+                "Outputs:               {} (longest gap {}, mean {})",
+                self.output_intervals.len(),
+                longest,
+                self.output_intervals.iter().sum::<u64>() / self.output_intervals.len() as u64
+            )
+            .unwrap(),
+            None => writeln!(report, "Outputs:               0").unwrap(),
+        }
+        writeln!(report, "Since last output:     {}", self.since_output).unwrap();
+
+        writeln!(report, "\nBy opcode:").unwrap();
+        let mut opcodes: Vec<_> = self.by_opcode.iter().collect();
+        opcodes.sort_by_key(|&(_, &count)| Reverse(count));
+        for (mnemonic, &count) in opcodes {
+            writeln!(report, "  {:<4} {}", mnemonic, self.share(count)).unwrap();
+        }
+
+        // Group instructions into the blocks of the control-flow graph, which
+        // is where loops show up. Any run outside the blocks found statically
+        // are grouped together.
+        let cfg = isa.cfg(program);
+        let mut blocks: BTreeMap<Option<usize>, u64> = BTreeMap::new();
+        for (&pc, &count) in &self.by_pc {
+            let block = cfg
+                .blocks
+                .range(..=pc.max(0) as usize)
+                .next_back()
+                .filter(|(_, block)| (pc as usize) < block.end)
+                .map(|(&start, _)| start);
+            *blocks.entry(block).or_insert(0) += count;
+        }
+        let mut blocks: Vec<_> = blocks.into_iter().collect();
+        blocks.sort_by_key(|&(_, count)| Reverse(count));
+
+        writeln!(report, "\nHot blocks:").unwrap();
+        for (start, count) in blocks.into_iter().take(top) {
+            let name = match start {
+                Some(start) => {
+                    let block = &cfg.blocks[&start];
+                    let function = match block.function {
+                        0 => "main".to_string(),
+                        entry => format!("fn {}", entry),
+                    };
+                    format!("{:04}..{:04} in {}", start, block.end, function)
+                }
+                None => "(outside known blocks)".to_string(),
+            };
+            writeln!(report, "  {}  {}", self.share(count), name).unwrap();
+        }
+
+        writeln!(report, "\nHot instructions:").unwrap();
+        let mut pcs: Vec<_> = self.by_pc.iter().collect();
+        pcs.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (&pc, &count) in pcs.into_iter().take(top) {
+            let line = match isa.disassemble_from(program, pc.max(0) as usize, 1).pop() {
+                Some(line) if pc >= 0 => line.to_string(),
+                _ => format!("{:04}: ?", pc),
+            };
+            writeln!(report, "  {}  {}", self.share(count), line).unwrap();
+        }
+
+        report
+    }
+}
+
+impl<I, O, M> Intcode<I, O, M> {
+    /// Starts profiling, throwing away any profile gathered so far.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::new(self.relative_base));
+    }
+
+    /// Stops profiling, and returns the profile gathered.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Effect, Instruction, Memory};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // Counts down from 3, outputting each value, then halts.
+    const COUNTDOWN: &str = "109,50,1101,3,0,20,4,20,1001,20,-1,20,1005,20,6,99";
+
+    #[test]
+    fn counts_instructions() {
+        init();
+        let mut program = Intcode::from(COUNTDOWN).unwrap();
+        program.start_profile();
+        program.run().unwrap();
+        let profile = program.take_profile().unwrap();
+
+        assert_eq!(12, profile.instructions);
+        assert_eq!(Some(&3), profile.by_pc.get(&6));
+        assert_eq!(Some(&1), profile.by_pc.get(&15));
+        assert_eq!(Some(&3), profile.by_opcode.get("OUT"));
+        assert_eq!(Some(&4), profile.by_opcode.get("ADD"));
+        assert_eq!(vec![3, 3, 3], profile.output_intervals);
+        assert_eq!(20, profile.max_address);
+        assert_eq!((0, 50), profile.relative_base_range);
+        assert_eq!(None, program.take_profile());
+    }
+
+    #[test]
+    fn report() {
+        init();
+        let mut program = Intcode::from(COUNTDOWN).unwrap();
+        let image = program.program.to_vec();
+        program.start_profile();
+        program.run().unwrap();
+        let report = program.take_profile().unwrap().report(program.instruction_set(), &image, 2);
+
+        assert!(report.starts_with("Instructions executed: 12\n"));
+        assert!(report.contains("Outputs:               3 (longest gap 3, mean 3)\n"));
+        assert!(report.contains("\nHot blocks:\n             9  75.00%  0006..0015 in main\n"));
+        assert!(report.contains("\nHot instructions:\n             3  25.00%  0006: OUT [20]\n"));
+    }
+
+    #[test]
+    fn dialect_report() {
+        init();
+        // The countdown again, with a dialect's SUB doing the decrement.
+        let dialect = InstructionSet::standard().with(Instruction::new(10, "SUB", 3, Some(2), |p| {
+            p[0].checked_sub(p[1]).map(Effect::Store)
+        }));
+        let mut program = Intcode::from("1101,3,0,20,4,20,1010,20,1,20,1005,20,4,99").unwrap();
+        program.set_instruction_set(dialect);
+        let image = program.program.to_vec();
+        program.start_profile();
+        program.run().unwrap();
+        let report = program.take_profile().unwrap().report(program.instruction_set(), &image, 2);
+
+        assert!(report.contains("\nHot blocks:\n             9  81.82%  0004..0013 in main\n"), "{}", report);
+        assert!(report.contains("  0006: SUB [20], #1 -> [20]\n"), "{}", report);
+    }
+}
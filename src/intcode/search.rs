@@ -0,0 +1,222 @@
+//! Brute-force searches over many runs of a machine, spread across threads.
+//!
+//! Results are the same as searching in order: the first match found is the
+//! one earliest in the candidate list, and ties for the maximum go to the
+//! earliest candidate too.
+
+use super::Intcode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Hands out candidates, in order, to a worker on each core. Candidates after
+/// index `limit` are skipped, so lowering it stops the search early.
+fn run_parallel<C, It, F>(candidates: It, limit: &AtomicUsize, work: F)
+where
+    C: Send,
+    It: Iterator<Item = C> + Send,
+    F: Fn(usize, C) + Sync,
+{
+    let source = Mutex::new(candidates.enumerate());
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let next = source.lock().unwrap().next();
+                match next {
+                    Some((index, candidate)) if index < limit.load(Ordering::SeqCst) => {
+                        work(index, candidate)
+                    }
+                    _ => break,
+                }
+            });
+        }
+    });
+}
+
+/// Finds the first candidate for which `test` is true.
+pub fn find_first_with<C, It, T>(candidates: It, test: T) -> Option<C>
+where
+    C: Send,
+    It: Iterator<Item = C> + Send,
+    T: Fn(&C) -> bool + Sync,
+{
+    let limit = AtomicUsize::new(usize::MAX);
+    let found = Mutex::new(None);
+
+    run_parallel(candidates, &limit, |index, candidate| {
+        if test(&candidate) {
+            limit.fetch_min(index, Ordering::SeqCst);
+            let mut found = found.lock().unwrap();
+            match *found {
+                Some((best, _)) if best < index => {}
+                _ => *found = Some((index, candidate)),
+            }
+        }
+    });
+
+    found.into_inner().unwrap().map(|(_, candidate)| candidate)
+}
+
+/// Finds the candidate with the highest value of `objective`, ignoring any
+/// for which it gives None.
+pub fn find_max_with<C, K, It, O>(candidates: It, objective: O) -> Option<(C, K)>
+where
+    C: Send,
+    K: Ord + Send,
+    It: Iterator<Item = C> + Send,
+    O: Fn(&C) -> Option<K> + Sync,
+{
+    let limit = AtomicUsize::new(usize::MAX);
+    let best = Mutex::new(None);
+
+    run_parallel(candidates, &limit, |index, candidate| {
+        if let Some(value) = objective(&candidate) {
+            let mut best = best.lock().unwrap();
+            let better = match &*best {
+                Some((best_index, _, best_value)) => {
+                    value > *best_value || (value == *best_value && index < *best_index)
+                }
+                None => true,
+            };
+            if better {
+                *best = Some((index, candidate, value));
+            }
+        }
+    });
+
+    best.into_inner()
+        .unwrap()
+        .map(|(_, candidate, value)| (candidate, value))
+}
+
+/// Runs a copy of `base` for each candidate, after letting `prepare` patch
+/// its memory or feed it input, and returns the first candidate whose
+/// finished machine passes `test`. Candidates whose runs fail, or don't halt
+/// within `max_steps` instructions, are skipped.
+pub fn find_first<C, It, P, T>(
+    base: &Intcode,
+    max_steps: u64,
+    candidates: It,
+    prepare: P,
+    test: T,
+) -> Option<C>
+where
+    C: Send,
+    It: Iterator<Item = C> + Send,
+    P: Fn(&mut Intcode, &C) + Sync,
+    T: Fn(&Intcode) -> bool + Sync,
+{
+    find_first_with(candidates, |candidate| {
+        run_candidate(base, max_steps, candidate, &prepare).is_some_and(|machine| test(&machine))
+    })
+}
+
+/// Like `find_first`, but returns the candidate whose finished machine gives
+/// the highest value of `objective`.
+pub fn find_max<C, K, It, P, O>(
+    base: &Intcode,
+    max_steps: u64,
+    candidates: It,
+    prepare: P,
+    objective: O,
+) -> Option<(C, K)>
+where
+    C: Send,
+    K: Ord + Send,
+    It: Iterator<Item = C> + Send,
+    P: Fn(&mut Intcode, &C) + Sync,
+    O: Fn(&Intcode) -> K + Sync,
+{
+    find_max_with(candidates, |candidate| {
+        run_candidate(base, max_steps, candidate, &prepare).map(|machine| objective(&machine))
+    })
+}
+
+fn run_candidate<C, P>(base: &Intcode, max_steps: u64, candidate: &C, prepare: &P) -> Option<Intcode>
+where
+    P: Fn(&mut Intcode, &C),
+{
+    let mut machine = base.clone();
+    prepare(&mut machine, candidate);
+    match machine.run_with_limit(max_steps) {
+        Ok(()) => Some(machine),
+        Err(e) => {
+            debug!("Skipping candidate: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn finds_earliest_match() {
+        init();
+        assert_eq!(
+            Some(700),
+            find_first_with(0..100_000, |&n| n % 7 == 0 && n >= 700)
+        );
+        assert_eq!(None, find_first_with(0..1000, |&n| n > 1000));
+    }
+
+    #[test]
+    fn finds_earliest_max() {
+        init();
+        assert_eq!(Some((9, 9)), find_max_with(0..1000, |&n| Some(n % 10)));
+        assert_eq!(Some((999, 999)), find_max_with(0..1000, |&n| Some(n)));
+        assert_eq!(None, find_max_with(0..1000, |_| None::<i32>));
+    }
+
+    #[test]
+    fn patch_and_run() {
+        init();
+        // Day 2 style: find the noun and verb that multiply to 391. Each cell
+        // after the program holds its own address.
+        let cells: Vec<String> = (5..30).map(|n| n.to_string()).collect();
+        let base = Intcode::from(&format!("2,0,0,0,99,{}", cells.join(","))).unwrap();
+        let candidates = (5..30).flat_map(|noun| (5..30).map(move |verb| (noun, verb)));
+        let patch = |machine: &mut Intcode, &(noun, verb): &(i64, i64)| {
+            machine.program[1] = noun;
+            machine.program[2] = verb;
+        };
+        assert_eq!(
+            Some((17, 23)),
+            find_first(&base, 100, candidates.clone(), patch, |m| m.program[0] == 391)
+        );
+        assert_eq!(
+            Some(((29, 29), 841)),
+            find_max(&base, 100, candidates, patch, |m| m.program[0])
+        );
+    }
+
+    #[test]
+    fn failed_runs_are_skipped() {
+        init();
+        // Candidate 4 doesn't get any input, so its run fails.
+        let base = Intcode::from("3,0,4,0,99").unwrap();
+        let feed = |machine: &mut Intcode, &n: &i64| {
+            if n != 4 {
+                machine.input.push_back(n)
+            }
+        };
+        assert_eq!(Some((3, 3)), find_max(&base, 100, 0..5, feed, |m| m.output[0]));
+    }
+
+    #[test]
+    fn endless_runs_are_skipped() {
+        init();
+        // Jumps to itself forever if the input is zero.
+        let base = Intcode::from("3,6,1006,6,2,99,0").unwrap();
+        let feed = |machine: &mut Intcode, &n: &i64| machine.input.push_back(n);
+        assert_eq!(Some(1), find_first(&base, 100, 0..3, feed, |_| true));
+        assert_eq!(None, find_first(&base, 100, 0..1, feed, |_| true));
+    }
+}
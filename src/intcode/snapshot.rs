@@ -0,0 +1,285 @@
+//! Saving and loading the complete state of a machine.
+//!
+//! Snapshots are text, with a version header and one field per line:
+//!
+//! ```text
+//! intcode-snapshot 3
+//! pc 4
+//! relative_base 0
+//! level 9
+//! input 7,8
+//! output
+//! memory 1,0,0,0,99
+//! runs 1000:5,6 1000000000000:7
+//! extent 1000000000001
+//! ```
+//!
+//! `memory` is the program image. Anything written beyond it is kept as runs
+//! of non-zero cells, each an address and the values from there on, so one
+//! write to a huge address doesn't make a huge snapshot.
+//!
+//! `level` is the feature level of the instruction set, by day. A machine is
+//! restored with the standard instructions for that level, so any a dialect
+//! adds are lost, and have to be set up again with `set_instruction_set`.
+//!
+//! Older snapshots can still be loaded: version 1 only has `memory`, and
+//! neither it nor version 2 has `level`, so those restore at day 9.
+//!
+//! Watchpoints, traces and history aren't part of the snapshot.
+
+use super::{read_file, FeatureLevel, InstructionSet, Intcode, IntcodeError, Memory};
+use std::convert::TryFrom;
+
+const HEADER: &str = "intcode-snapshot";
+const VERSION: u32 = 3;
+
+fn join(values: impl Iterator<Item = i64>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn invalid(message: String) -> IntcodeError {
+    IntcodeError::InvalidSnapshot(message)
+}
+
+/// Parses a comma-separated list of numbers, which may be empty.
+fn parse_list(field: &str, value: &str) -> Result<Vec<i64>, IntcodeError> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+    value
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid(format!("invalid {}: {:?}", field, value)))
+}
+
+/// Parses runs of memory, each an address and a list of values, separated by
+/// spaces.
+fn parse_runs(value: &str) -> Result<Vec<(usize, Vec<i64>)>, IntcodeError> {
+    value
+        .split_whitespace()
+        .map(|run| {
+            let mut parts = run.splitn(2, ':');
+            let start = parts.next().unwrap_or_default().parse();
+            match (start, parts.next()) {
+                (Ok(start), Some(values)) => Ok((start, parse_list("runs", values)?)),
+                _ => Err(invalid(format!("invalid run: {:?}", run))),
+            }
+        })
+        .collect()
+}
+
+/// Whether some text looks like a snapshot rather than a program.
+pub fn is_snapshot(text: &str) -> bool {
+    text.starts_with(HEADER)
+}
+
+impl Intcode {
+    /// Captures the complete state of the machine as text.
+    pub fn snapshot(&self) -> String {
+        format!(
+            "{} {}\npc {}\nrelative_base {}\nlevel {}\ninput {}\noutput {}\nmemory {}\nruns {}\nextent {}\n",
+            HEADER,
+            VERSION,
+            self.pc,
+            self.relative_base,
+            self.isa.level().day(),
+            join(self.input.iter().cloned()),
+            join(self.output.iter().cloned()),
+            join(self.program.image().iter().cloned()),
+            self.program
+                .runs()
+                .into_iter()
+                .map(|(start, values)| format!("{}:{}", start, join(values.into_iter())))
+                .collect::<Vec<_>>()
+                .join(" "),
+            self.program.extent(),
+        )
+    }
+
+    /// Rebuilds a machine from a snapshot.
+    pub fn from_snapshot(text: &str) -> Result<Self, IntcodeError> {
+        let mut lines = text.lines();
+
+        let version = match lines.next().map(|line| line.split_whitespace().collect::<Vec<_>>()) {
+            Some(ref words) if words.len() == 2 && words[0] == HEADER => match words[1].parse() {
+                Ok(version) if (1..=VERSION).contains(&version) => version,
+                _ => return Err(invalid(format!("unsupported version {}", words[1]))),
+            },
+            _ => return Err(invalid("missing header".to_string())),
+        };
+
+        let mut machine = Intcode::new(Vec::new());
+        let mut runs = Vec::new();
+        let mut extent = 0;
+        let mut level = FeatureLevel::Day9;
+        let mut seen = Vec::new();
+
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            let field = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default().trim();
+            let number = || {
+                value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid {}: {:?}", field, value)))
+            };
+
+            match field {
+                "pc" => machine.pc = number()?,
+                "relative_base" => machine.relative_base = number()?,
+                "input" => machine.input = parse_list(field, value)?.into_iter().collect(),
+                "output" => machine.output = parse_list(field, value)?,
+                "memory" => machine.program = parse_list(field, value)?.into(),
+                "level" => {
+                    level = value
+                        .parse()
+                        .ok()
+                        .and_then(FeatureLevel::from_day)
+                        .ok_or_else(|| invalid(format!("invalid level: {:?}", value)))?
+                }
+                "runs" => runs = parse_runs(value)?,
+                "extent" => {
+                    extent = usize::try_from(number()?)
+                        .map_err(|_| invalid(format!("invalid extent: {:?}", value)))?
+                }
+                _ => return Err(invalid(format!("unknown field {:?}", field))),
+            }
+            seen.push(field);
+        }
+
+        let required: &[&str] = match version {
+            1 => &["pc", "relative_base", "input", "output", "memory"],
+            2 => &["pc", "relative_base", "input", "output", "memory", "runs", "extent"],
+            _ => &["pc", "relative_base", "level", "input", "output", "memory", "runs", "extent"],
+        };
+        for field in required {
+            if !seen.contains(field) {
+                return Err(invalid(format!("missing {}", field)));
+            }
+        }
+
+        for (start, values) in runs {
+            for (offset, value) in values.into_iter().enumerate() {
+                machine.program.store(start + offset, value);
+            }
+        }
+        machine.program.extend_to(extent);
+        // Now memory's all there, so the decode cache can be filled from it.
+        // Day 9 machines keep sharing the standard set, as a fresh one would.
+        if level == FeatureLevel::Day9 {
+            machine.fill_decode_cache();
+        } else {
+            machine.set_instruction_set(InstructionSet::for_level(level));
+        }
+
+        Ok(machine)
+    }
+
+    /// Saves a snapshot to a file.
+    pub fn save_snapshot(&self, name: &str) -> std::io::Result<()> {
+        std::fs::write(name, self.snapshot())
+    }
+
+    /// Loads a snapshot from a file.
+    pub fn load_snapshot(name: &str) -> Result<Self, IntcodeError> {
+        let data = read_file(name)?;
+
+        Self::from_snapshot(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn save_and_restore() {
+        init();
+        let mut program = Intcode::from("3,0,4,0,109,7,3,1,4,1,99").unwrap();
+        program.input.push_back(5);
+        program.run_until_input_or_halt().unwrap();
+
+        let snapshot = program.snapshot();
+        assert_eq!(
+            "intcode-snapshot 3\npc 6\nrelative_base 7\nlevel 9\ninput \noutput 5\nmemory 5,0,4,0,109,7,3,1,4,1,99\nruns \nextent 11\n",
+            snapshot
+        );
+
+        let mut restored = Intcode::from_snapshot(&snapshot).unwrap();
+        assert_eq!(6, restored.pc());
+        assert_eq!(7, restored.relative_base());
+        restored.input.push_back(9);
+        restored.run().unwrap();
+        assert_eq!(vec![5, 9], restored.output);
+    }
+
+    #[test]
+    fn bad_snapshots() {
+        init();
+        assert_eq!(
+            Err(IntcodeError::InvalidSnapshot("unsupported version 4".to_string())),
+            Intcode::from_snapshot("intcode-snapshot 4\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(IntcodeError::InvalidSnapshot("missing header".to_string())),
+            Intcode::from_snapshot("1,2,3").map(|_| ())
+        );
+        assert_eq!(
+            Err(IntcodeError::InvalidSnapshot("missing memory".to_string())),
+            Intcode::from_snapshot("intcode-snapshot 1\npc 0\nrelative_base 0\ninput\noutput\n")
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(IntcodeError::InvalidSnapshot("invalid run: \"5\"".to_string())),
+            Intcode::from_snapshot("intcode-snapshot 2\npc 0\nrelative_base 0\ninput\noutput\nmemory\nruns 5\nextent 0\n")
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(IntcodeError::InvalidSnapshot("invalid extent: \"-1\"".to_string())),
+            Intcode::from_snapshot("intcode-snapshot 2\npc 0\nrelative_base 0\ninput\noutput\nmemory\nruns\nextent -1\n")
+                .map(|_| ())
+        );
+    }
+
+    #[test]
+    fn huge_memory() {
+        init();
+        let mut program = Intcode::from("109,1000000000000,21101,3,4,0,21101,0,0,5,99").unwrap();
+        program.run().unwrap();
+
+        let snapshot = program.snapshot();
+        assert!(snapshot.ends_with("\nruns 1000000000000:7\nextent 1000000000006\n"));
+        assert_eq!(program.program, Intcode::from_snapshot(&snapshot).unwrap().program);
+    }
+
+    #[test]
+    fn keeps_feature_level() {
+        init();
+        let mut program = Intcode::from("1,0,0,0,99").unwrap();
+        program.set_instruction_set(InstructionSet::day2());
+        let snapshot = program.snapshot();
+        assert!(snapshot.contains("\nlevel 2\n"));
+
+        let restored = Intcode::from_snapshot(&snapshot).unwrap();
+        assert_eq!(FeatureLevel::Day2, restored.instruction_set().level());
+        assert_eq!(
+            Err(IntcodeError::InvalidSnapshot("invalid level: \"3\"".to_string())),
+            Intcode::from_snapshot(&snapshot.replace("level 2", "level 3")).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn version_1() {
+        init();
+        let restored = Intcode::from_snapshot(
+            "intcode-snapshot 1\npc 2\nrelative_base 0\ninput\noutput\nmemory 104,5,99\n",
+        )
+        .unwrap();
+        assert_eq!(vec![104, 5, 99], restored.program);
+    }
+}
@@ -0,0 +1,343 @@
+//! Running each machine in a network on its own thread, connected by
+//! channels, as an alternative to the round-robin scheduler in `network`.
+//!
+//! Only rings and chains are supported; packet buses need the round-robin
+//! `Network`.
+
+use super::network::{MachineError, NetworkState};
+use super::{InputSource, Intcode, OutputSink, StepResult};
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// How many instructions a machine runs between checks for a shutdown, so
+/// that one which never waits for input can still be stopped.
+const SHUTDOWN_CHECK: u64 = 1000;
+
+/// What the supervisor knows about each machine.
+#[derive(Debug)]
+struct State {
+    running: Vec<bool>,
+    blocked: Vec<bool>,
+    /// Values sent to each machine which it hasn't received yet.
+    in_flight: Vec<usize>,
+    failed: bool,
+    shutdown: bool,
+}
+
+impl State {
+    /// Every machine still running is waiting for input, and none is coming.
+    fn deadlocked(&self) -> bool {
+        (0..self.running.len())
+            .filter(|&ii| self.running[ii])
+            .all(|ii| self.blocked[ii] && self.in_flight[ii] == 0)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn update<F: FnOnce(&mut State)>(&self, f: F) {
+        f(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+    }
+}
+
+/// Input for a machine, which blocks until a value arrives, keeping the
+/// supervisor informed.
+#[derive(Debug)]
+pub struct ChannelInput {
+    machine: usize,
+    receiver: Receiver<i64>,
+    shared: Arc<Shared>,
+}
+
+impl InputSource for ChannelInput {
+    fn next_input(&mut self) -> Option<i64> {
+        let machine = self.machine;
+        self.shared.update(|state| state.blocked[machine] = true);
+        let value = self.receiver.recv().ok();
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.blocked[machine] = false;
+        if state.shutdown {
+            return None;
+        }
+        if value.is_some() {
+            state.in_flight[machine] -= 1;
+        }
+        value
+    }
+}
+
+/// Output from a machine, either to the next machine or, at the end of a
+/// chain, collected up.
+#[derive(Debug)]
+pub struct ChannelOutput {
+    target: Option<(usize, Sender<i64>)>,
+    collected: Vec<i64>,
+    last: Option<i64>,
+    shared: Arc<Shared>,
+}
+
+impl OutputSink for ChannelOutput {
+    fn push_output(&mut self, value: i64) {
+        self.last = Some(value);
+        match &self.target {
+            Some((machine, sender)) => {
+                let machine = *machine;
+                self.shared.update(|state| state.in_flight[machine] += 1);
+                // If the other machine has gone, nobody cares about this.
+                let _ = sender.send(value);
+            }
+            None => self.collected.push(value),
+        }
+    }
+}
+
+/// The results of running a threaded network.
+#[derive(Debug, Clone)]
+pub struct ThreadedOutcome {
+    pub state: NetworkState,
+    /// The machines, as they were when they stopped.
+    pub machines: Vec<Intcode>,
+    /// The last value output by each machine.
+    pub last_output: Vec<Option<i64>>,
+    /// Values that came out of the end of a chain.
+    pub output: Vec<i64>,
+}
+
+/// A network of machines, each of which will run on its own thread.
+#[derive(Debug)]
+pub struct ThreadedNetwork {
+    machines: Vec<Intcode>,
+    ring: bool,
+    initial: Vec<Vec<i64>>,
+}
+
+impl ThreadedNetwork {
+    /// Each machine's output goes to the next, and the last machine's goes
+    /// back round to the first.
+    pub fn ring(machines: Vec<Intcode>) -> Self {
+        let initial = vec![Vec::new(); machines.len()];
+        Self {
+            machines,
+            ring: true,
+            initial,
+        }
+    }
+
+    /// Each machine's output goes to the next, and the last machine's output
+    /// is collected.
+    pub fn chain(machines: Vec<Intcode>) -> Self {
+        let initial = vec![Vec::new(); machines.len()];
+        Self {
+            machines,
+            ring: false,
+            initial,
+        }
+    }
+
+    /// Queues up input for a machine before the network starts.
+    pub fn send(&mut self, machine: usize, value: i64) {
+        self.initial[machine].push(value);
+    }
+
+    /// Runs every machine on its own thread until they've all halted, or
+    /// they're deadlocked waiting for each other. If any machine fails, the
+    /// rest are stopped and the first error is returned.
+    pub fn run(self) -> Result<ThreadedOutcome, MachineError> {
+        let n = self.machines.len();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                running: vec![true; n],
+                blocked: vec![false; n],
+                in_flight: vec![0; n],
+                failed: false,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..n).map(|_| channel()).unzip();
+        for (ii, values) in self.initial.into_iter().enumerate() {
+            for value in values {
+                shared.state.lock().unwrap().in_flight[ii] += 1;
+                senders[ii].send(value).unwrap();
+            }
+        }
+
+        let mut handles = Vec::new();
+        for (ii, (machine, receiver)) in self.machines.into_iter().zip(receivers).enumerate() {
+            let next = if self.ring { Some((ii + 1) % n) } else { Some(ii + 1).filter(|&j| j < n) };
+            let input = ChannelInput {
+                machine: ii,
+                receiver,
+                shared: shared.clone(),
+            };
+            let output = ChannelOutput {
+                target: next.map(|j| (j, senders[j].clone())),
+                collected: Vec::new(),
+                last: None,
+                shared: shared.clone(),
+            };
+            let mut machine = machine.with_io(input, output);
+            let shared = shared.clone();
+
+            handles.push(thread::spawn(move || {
+                let mut steps = 0u64;
+                let result = loop {
+                    match machine.step() {
+                        Ok(StepResult::Halt) => break Ok(()),
+                        // We only get here if the supervisor is shutting us down.
+                        Ok(StepResult::NeedInput) => break Ok(()),
+                        Ok(_) => {}
+                        Err(e) => break Err(e),
+                    }
+                    steps += 1;
+                    if steps.is_multiple_of(SHUTDOWN_CHECK) && shared.state.lock().unwrap().shutdown {
+                        break Ok(());
+                    }
+                };
+                shared.update(|state| {
+                    state.running[ii] = false;
+                    state.failed |= result.is_err();
+                });
+                (machine, result)
+            }));
+        }
+
+        // Wait until everything has finished, or we need to stop it.
+        let mut deadlocked = false;
+        {
+            let mut state = shared.state.lock().unwrap();
+            while state.running.iter().any(|&r| r) {
+                if state.failed || state.deadlocked() {
+                    deadlocked = !state.failed;
+                    state.shutdown = true;
+                    break;
+                }
+                state = shared.changed.wait(state).unwrap();
+            }
+        }
+        // Wake up anything still waiting for input so it can see we're done.
+        for sender in &senders {
+            let _ = sender.send(0);
+        }
+
+        let mut outcome = ThreadedOutcome {
+            state: if deadlocked { NetworkState::Idle } else { NetworkState::Halted },
+            machines: Vec::new(),
+            last_output: Vec::new(),
+            output: Vec::new(),
+        };
+        let mut error: Option<MachineError> = None;
+        for (ii, handle) in handles.into_iter().enumerate() {
+            let (machine, result) = handle.join().expect("Machine thread panicked");
+            if let (Err(e), None) = (result, &error) {
+                error = Some(MachineError { machine: ii, error: e });
+            }
+            outcome.last_output.push(machine.output.last);
+            outcome.output.extend(machine.output.collected.iter());
+            outcome.machines.push(machine.with_io(VecDeque::new(), Vec::new()));
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(outcome),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, IntcodeError};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn ring_of_amplifiers() {
+        init();
+        let program = Intcode::from(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        )
+        .unwrap();
+        let mut network = ThreadedNetwork::ring(vec![program; 5]);
+        for (ii, &phase) in [9, 8, 7, 6, 5].iter().enumerate() {
+            network.send(ii, phase);
+        }
+        network.send(0, 0);
+
+        let outcome = network.run().unwrap();
+        assert_eq!(NetworkState::Halted, outcome.state);
+        assert_eq!(Some(139629729), outcome.last_output[4]);
+    }
+
+    #[test]
+    fn chain_of_amplifiers() {
+        init();
+        let program = Intcode::from("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0").unwrap();
+        let mut network = ThreadedNetwork::chain(vec![program; 5]);
+        for (ii, &phase) in [4, 3, 2, 1, 0].iter().enumerate() {
+            network.send(ii, phase);
+        }
+        network.send(0, 0);
+
+        let outcome = network.run().unwrap();
+        assert_eq!(NetworkState::Halted, outcome.state);
+        assert_eq!(vec![43210], outcome.output);
+    }
+
+    #[test]
+    fn deadlock() {
+        init();
+        // Each machine passes on one value, then waits for a second that
+        // never comes.
+        let program = Intcode::from(&assemble("in -> [100]\nout [100]\nin -> [100]\nhlt").unwrap()).unwrap();
+        let mut network = ThreadedNetwork::ring(vec![program; 3]);
+        network.send(0, 1);
+
+        let outcome = network.run().unwrap();
+        assert_eq!(NetworkState::Idle, outcome.state);
+        assert_eq!(vec![Some(1), Some(1), Some(1)], outcome.last_output);
+    }
+
+    #[test]
+    fn failure_stops_everything() {
+        init();
+        let waiter = Intcode::from("3,100,99").unwrap();
+        let broken = Intcode::from("42").unwrap();
+        let network = ThreadedNetwork::ring(vec![waiter.clone(), broken, waiter]);
+        assert_eq!(
+            Err(MachineError {
+                machine: 1,
+                error: IntcodeError::UnknownOpcode { pc: 0, opcode: 42 }
+            }),
+            network.run().map(|outcome| outcome.state)
+        );
+    }
+
+    #[test]
+    fn failure_stops_busy_machines() {
+        init();
+        // Jumps to itself forever, never asking for input.
+        let busy = Intcode::from("1105,1,0").unwrap();
+        let broken = Intcode::from("42").unwrap();
+        let network = ThreadedNetwork::chain(vec![busy, broken]);
+        assert_eq!(
+            Err(MachineError {
+                machine: 1,
+                error: IntcodeError::UnknownOpcode { pc: 0, opcode: 42 }
+            }),
+            network.run().map(|outcome| outcome.state)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Recording of execution traces, and tools to replay and compare them.
+//!
+//! Traces are saved as text, one instruction per line, like:
+//!
+//! ```text
+//! pc=8 ins=1005 params=1,22
+//! pc=22 ins=1002 params=8,125,20 write=20:1000
+//! pc=26 ins=4 params=1000 out=1000
+//! ```
+//!
+//! Only the fields that apply to an instruction are written: `write` is the
+//! address and value written, `in` and `out` are values read from input and
+//! pushed to output, and `rb` is the relative base after it changed.
+
+use super::{Intcode, IntcodeError, StepResult};
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+/// A single executed instruction.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TraceEvent {
+    pub pc: i64,
+    /// The instruction cell as it was when executed.
+    pub instruction: i64,
+    /// The parameters, resolved according to their modes.
+    pub params: Vec<i64>,
+    /// The address and value written, if any.
+    pub write: Option<(i64, i64)>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+    /// The new relative base, if it changed.
+    pub relative_base: Option<i64>,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pc={} ins={}", self.pc, self.instruction)?;
+        if !self.params.is_empty() {
+            let params: Vec<String> = self.params.iter().map(|p| p.to_string()).collect();
+            write!(f, " params={}", params.join(","))?;
+        }
+        if let Some((address, value)) = self.write {
+            write!(f, " write={}:{}", address, value)?;
+        }
+        if let Some(input) = self.input {
+            write!(f, " in={}", input)?;
+        }
+        if let Some(output) = self.output {
+            write!(f, " out={}", output)?;
+        }
+        if let Some(base) = self.relative_base {
+            write!(f, " rb={}", base)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TraceEvent {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut event = TraceEvent::default();
+        let number = |s: &str| s.parse::<i64>().map_err(|_| ());
+        let mut has_pc = false;
+
+        for field in input.split_whitespace() {
+            let mut parts = field.splitn(2, '=');
+            let (key, value) = (parts.next().ok_or(())?, parts.next().ok_or(())?);
+            match key {
+                "pc" => {
+                    event.pc = number(value)?;
+                    has_pc = true;
+                }
+                "ins" => event.instruction = number(value)?,
+                "params" => event.params = value.split(',').map(number).collect::<Result<_, _>>()?,
+                "write" => {
+                    let mut parts = value.splitn(2, ':');
+                    let address = number(parts.next().ok_or(())?)?;
+                    event.write = Some((address, number(parts.next().ok_or(())?)?));
+                }
+                "in" => event.input = Some(number(value)?),
+                "out" => event.output = Some(number(value)?),
+                "rb" => event.relative_base = Some(number(value)?),
+                _ => return Err(()),
+            }
+        }
+
+        // Every event has a pc, so anything without one isn't an event.
+        if has_pc {
+            Ok(event)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Writes a trace out, one event per line.
+pub fn write_trace<W: Write>(events: &[TraceEvent], mut writer: W) -> std::io::Result<()> {
+    for event in events {
+        writeln!(writer, "{}", event)?;
+    }
+    Ok(())
+}
+
+/// Reads back a trace written by `write_trace`, skipping blank lines. A line
+/// that can't be parsed gives a ParseError with its (0-based) line number,
+/// and one that can't be read at all gives an Io error.
+pub fn read_trace<R: BufRead>(reader: R) -> Result<Vec<TraceEvent>, IntcodeError> {
+    let mut events = Vec::new();
+    for (position, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| IntcodeError::Io {
+            name: format!("trace line {}", position),
+            message: e.to_string(),
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(line.parse().map_err(|_| IntcodeError::ParseError {
+            position,
+            entry: line,
+        })?);
+    }
+    Ok(events)
+}
+
+/// Finds the index of the first event where two traces differ. If one trace
+/// is a prefix of the other, that's where the shorter one stops.
+pub fn first_difference(a: &[TraceEvent], b: &[TraceEvent]) -> Option<usize> {
+    match a.iter().zip(b).position(|(a, b)| a != b) {
+        Some(index) => Some(index),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+/// Re-executes a program, feeding it the input recorded in a trace, and
+/// checks it does the same thing again. Returns the index of the first event
+/// that came out differently, if any.
+pub fn replay(mut machine: Intcode, events: &[TraceEvent]) -> Result<Option<usize>, IntcodeError> {
+    machine.input.extend(events.iter().filter_map(|event| event.input));
+    machine.start_trace();
+
+    for _ in events {
+        match machine.step()? {
+            StepResult::Halt | StepResult::NeedInput => break,
+            _ => {}
+        }
+    }
+
+    Ok(first_difference(events, &machine.take_trace()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn traced(program: &str, input: i64) -> Vec<TraceEvent> {
+        let mut program = Intcode::from(program).unwrap();
+        program.input.push_back(input);
+        program.start_trace();
+        program.run().unwrap();
+        program.take_trace()
+    }
+
+    #[test]
+    fn records_events() {
+        init();
+        let events = traced("109,10,203,1,204,1,99", 6);
+        let lines: Vec<String> = events.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "pc=0 ins=109 params=10 rb=10",
+                "pc=2 ins=203 params=11 write=11:6 in=6",
+                "pc=4 ins=204 params=6 out=6",
+                "pc=6 ins=99",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        init();
+        let events = traced("109,10,203,1,204,1,99", 6);
+        let mut saved = Vec::new();
+        write_trace(&events, &mut saved).unwrap();
+        assert_eq!(events, read_trace(&saved[..]).unwrap());
+    }
+
+    #[test]
+    fn load_damaged_traces() {
+        init();
+        // Blank lines are skipped, but the line numbers still count them.
+        let events = read_trace(&b"pc=0 ins=99\n\n  \npc=2 ins=99\n"[..]).unwrap();
+        assert_eq!(vec![0, 2], events.iter().map(|e| e.pc).collect::<Vec<_>>());
+        assert_eq!(
+            Err(IntcodeError::ParseError {
+                position: 2,
+                entry: "ins=99".to_string()
+            }),
+            read_trace(&b"pc=0 ins=99\n\nins=99\n"[..])
+        );
+
+        // Invalid UTF-8, as a truncated or corrupt file might have.
+        let result = read_trace(&b"pc=0 ins=99\npc=2 ins=\xff\n"[..]);
+        assert!(matches!(&result, Err(IntcodeError::Io { name, .. }) if name == "trace line 1"), "{:?}", result);
+    }
+
+    #[test]
+    fn replay_and_diff() {
+        init();
+        let program = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+        let seven = traced(program, 7);
+        let eight = traced(program, 8);
+
+        assert_eq!(None, replay(Intcode::from(program).unwrap(), &seven).unwrap());
+        assert_eq!(Some(0), first_difference(&seven, &eight));
+        assert_eq!(None, first_difference(&seven[1..3], &seven[1..3]));
+        assert_eq!(Some(2), first_difference(&seven[..2], &seven));
+    }
+}
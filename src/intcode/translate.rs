@@ -0,0 +1,554 @@
+//! Ahead-of-time translation of Intcode programs to Rust source.
+//!
+//! `translate` turns a program image into a Rust function: a `match` on the pc
+//! with an arm of straight-line code for each basic block. Once compiled into
+//! a crate, it can be run with `Intcode::run_translated`.
+//!
+//! Translated code only handles the common cases itself. When it reaches
+//! anything else (a halt, running out of input, an overflow, a jump it can't
+//! follow, or a write into its own code) it stops with the pc pointing at
+//! that instruction, unexecuted, and the interpreter takes over from there.
+
+use super::disasm::disassemble_from;
+use super::isa::standard_set;
+use super::{InputSource, Intcode, IntcodeError, Memory, Opcode, Operation, OutputSink, ParamMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// The signature of a translated program. It runs on a dense copy of memory,
+/// and returns the pc and relative base it stopped at.
+pub type Translated =
+    fn(&mut Vec<i64>, &mut dyn FnMut() -> Option<i64>, &mut dyn FnMut(i64)) -> (i64, i64);
+
+/// Translated code leaves writes at or above this address to the interpreter,
+/// rather than growing its memory to fit.
+const MEMORY_LIMIT: i64 = 1 << 20;
+
+/// What's been found out about a program by following its control flow.
+#[derive(Clone, Default)]
+struct Analysis {
+    /// Every address reached, with what's there. None if it doesn't decode.
+    instructions: BTreeMap<usize, Option<Operation>>,
+    /// Addresses reached other than by falling through, which need an arm in
+    /// the match.
+    block_starts: BTreeSet<usize>,
+    /// Cells holding instructions or their parameters.
+    code: BTreeSet<usize>,
+    /// Whether any jump goes to a computed address.
+    computed_jumps: bool,
+}
+
+/// Decodes a complete instruction from the image, if there is one.
+fn decode(image: &[i64], address: usize) -> Option<(Operation, &[i64])> {
+    Operation::decode_in(image, address, standard_set())
+}
+
+impl Analysis {
+    fn new(image: &[i64]) -> Self {
+        let mut analysis = Self::default();
+        analysis.explore(image, 0);
+
+        // Return addresses are usually pushed as constants, so if there are
+        // computed jumps, try starting from any constant the program stores.
+        // Those that don't decode cleanly all the way are given up on.
+        if analysis.computed_jumps {
+            let mut tried = BTreeSet::new();
+            loop {
+                let roots: Vec<usize> = analysis
+                    .stored_constants(image)
+                    .into_iter()
+                    .filter(|root| !analysis.instructions.contains_key(root) && tried.insert(*root))
+                    .collect();
+                if roots.is_empty() {
+                    break;
+                }
+                for root in roots {
+                    let mut trial = analysis.clone();
+                    if trial.explore(image, root) {
+                        analysis = trial;
+                    }
+                }
+            }
+        }
+
+        analysis
+    }
+
+    /// Follows control flow from an address, returning false if it reached
+    /// anything that doesn't decode.
+    fn explore(&mut self, image: &[i64], root: usize) -> bool {
+        let mut clean = true;
+        let mut pending = vec![root];
+        self.block_starts.insert(root);
+
+        while let Some(address) = pending.pop() {
+            if self.instructions.contains_key(&address) {
+                continue;
+            }
+            let decoded = decode(image, address);
+            self.instructions.insert(address, decoded.map(|(op, _)| op));
+            let (op, params) = match decoded {
+                Some(decoded) => decoded,
+                None => {
+                    clean = false;
+                    continue;
+                }
+            };
+
+            let next = address + 1 + op.num_params as usize;
+            self.code.extend(address..next);
+
+            if op.halts(params) {
+                continue;
+            }
+            match op.jump(params, standard_set()) {
+                Some(jump) => {
+                    // Jumps always end a block, so wherever they go next
+                    // needs its own arm.
+                    if jump.taken != Some(true) {
+                        self.block_starts.insert(next);
+                        pending.push(next);
+                    }
+                    if jump.taken != Some(false) {
+                        if jump.mode != ParamMode::Immediate {
+                            self.computed_jumps = true;
+                        } else if 0 <= jump.target && (jump.target as usize) < image.len() {
+                            self.block_starts.insert(jump.target as usize);
+                            pending.push(jump.target as usize);
+                        }
+                    }
+                }
+                None => pending.push(next),
+            }
+        }
+
+        clean
+    }
+
+    /// Constants stored by instructions that only read immediates, which
+    /// point somewhere in the image.
+    fn stored_constants(&self, image: &[i64]) -> Vec<usize> {
+        self.instructions
+            .iter()
+            .filter_map(|(&address, op)| {
+                let op = op.as_ref()?;
+                op.stored_constant(&image[address + 1..address + 1 + op.num_params as usize])
+            })
+            .filter(|&value| 0 <= value && (value as usize) < image.len())
+            .map(|value| value as usize)
+            .collect()
+    }
+
+    /// The code cells as inclusive ranges, for a `matches!` pattern.
+    fn code_ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for &address in &self.code {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == address => *end = address,
+                _ => ranges.push((address, address)),
+            }
+        }
+        ranges
+    }
+
+    /// Whether translated code can write to an address without checking.
+    fn writable(&self, address: i64) -> bool {
+        (0..MEMORY_LIMIT).contains(&address) && !self.code.contains(&(address as usize))
+    }
+}
+
+/// Translates the instruction at `address` into lines of Rust, returning
+/// whether it ends the block.
+fn translate_instruction(
+    analysis: &Analysis,
+    image: &[i64],
+    address: usize,
+    op: &Operation,
+    lines: &mut Vec<String>,
+) -> bool {
+    let exit = format!("return ({}, rb)", address);
+    let next = address + 1 + op.num_params as usize;
+    let params = &image[address + 1..next];
+
+    // Read the parameters into x0, x1... and work out the address written.
+    for (ii, (mode, &value)) in op.param_modes().iter().zip(params).enumerate() {
+        let line = match mode {
+            ParamMode::Immediate => format!("let x{}: i64 = {};", ii, value),
+            ParamMode::Position if value >= 0 => format!("let x{}: i64 = load(mem, {});", ii, value),
+            ParamMode::Relative => format!(
+                "let x{}: i64 = load(mem, match relative(rb, {}) {{ Some(a) => a, None => {} }});",
+                ii, value, exit
+            ),
+            ParamMode::Reference if analysis.writable(value) => format!("let a: i64 = {};", value),
+            ParamMode::RelativeReference => format!(
+                "let a: i64 = match relative(rb, {}) {{ Some(a) if writable(a) => a, _ => {} }};",
+                value, exit
+            ),
+            // A negative address, or a write into code or far beyond it.
+            _ => {
+                lines.push(format!("{};", exit));
+                return true;
+            }
+        };
+        lines.push(line);
+    }
+
+    match op.opcode {
+        Opcode::Add | Opcode::Multiply => {
+            let method = if op.opcode == Opcode::Add { "checked_add" } else { "checked_mul" };
+            lines.push(format!("let v = match x0.{}(x1) {{ Some(v) => v, None => {} }};", method, exit));
+            lines.push("store(mem, a, v);".to_string());
+        }
+        Opcode::StoreInput => {
+            lines.push(format!("let v = match input() {{ Some(v) => v, None => {} }};", exit));
+            lines.push("store(mem, a, v);".to_string());
+        }
+        Opcode::PushOutput => lines.push("output(x0);".to_string()),
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+            let test = if op.opcode == Opcode::JumpIfTrue { "!=" } else { "==" };
+            lines.push(format!("pc = if x0 {} 0 {{ x1 }} else {{ {} }};", test, next));
+            return true;
+        }
+        Opcode::LessThan => lines.push("store(mem, a, (x0 < x1) as i64);".to_string()),
+        Opcode::Equals => lines.push("store(mem, a, (x0 == x1) as i64);".to_string()),
+        Opcode::UpdateBase => {
+            lines.push(format!("rb = match rb.checked_add(x0) {{ Some(v) => v, None => {} }};", exit))
+        }
+        Opcode::Halt => {
+            lines.push(format!("{};", exit));
+            return true;
+        }
+        // Not something translated code knows how to do, so leave it to the
+        // interpreter.
+        Opcode::Custom => {
+            lines.push(format!("{};", exit));
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Translates a program image into the source of a Rust function called
+/// `name`, with the signature of `Translated`.
+///
+/// The function checks that the code it was translated from is still in
+/// memory when it's called, and leaves everything to the interpreter if not,
+/// so programs patched before running (as on day 2) are still run correctly.
+pub fn translate(program: &[i64], name: &str) -> String {
+    let analysis = Analysis::new(program);
+
+    let ranges: Vec<String> = analysis
+        .code_ranges()
+        .iter()
+        .map(|&(start, end)| format!("{}..={}", start, end))
+        .collect();
+    let image: Vec<String> = analysis
+        .code
+        .iter()
+        .map(|&address| format!("({}, {})", address, program[address]))
+        .collect();
+
+    let mut lines = vec![
+        format!("/// Translated from an Intcode program of {} cells.", program.len()),
+        "#[allow(unused, clippy::all)]".to_string(),
+        format!(
+            "pub fn {}(mem: &mut Vec<i64>, input: &mut dyn FnMut() -> Option<i64>, output: &mut dyn FnMut(i64)) -> (i64, i64) {{",
+            name
+        ),
+        format!("    const CODE: [(i64, i64); {}] = [{}];", image.len(), image.join(", ")),
+        "    fn load(mem: &[i64], address: i64) -> i64 {".to_string(),
+        "        mem.get(address as usize).copied().unwrap_or(0)".to_string(),
+        "    }".to_string(),
+        "    fn store(mem: &mut Vec<i64>, address: i64, value: i64) {".to_string(),
+        "        if address as usize >= mem.len() {".to_string(),
+        "            mem.resize(address as usize + 1, 0);".to_string(),
+        "        }".to_string(),
+        "        mem[address as usize] = value;".to_string(),
+        "    }".to_string(),
+        "    fn relative(rb: i64, offset: i64) -> Option<i64> {".to_string(),
+        "        rb.checked_add(offset).filter(|&a| a >= 0)".to_string(),
+        "    }".to_string(),
+        "    fn writable(address: i64) -> bool {".to_string(),
+        format!(
+            "        address < {} && !matches!(address, {})",
+            MEMORY_LIMIT,
+            if ranges.is_empty() { "_ if false".to_string() } else { ranges.join(" | ") }
+        ),
+        "    }".to_string(),
+        "".to_string(),
+        "    if CODE.iter().any(|&(address, value)| load(mem, address) != value) {".to_string(),
+        "        return (0, 0);".to_string(),
+        "    }".to_string(),
+        "    let mut pc: i64 = 0;".to_string(),
+        "    let mut rb: i64 = 0;".to_string(),
+        "    loop {".to_string(),
+        "        match pc {".to_string(),
+    ];
+
+    for &start in &analysis.block_starts {
+        lines.push(format!("            {} => {{", start));
+        let mut body = Vec::new();
+        let mut address = start;
+        loop {
+            let op = match analysis.instructions.get(&address) {
+                Some(Some(op)) => op,
+                _ => {
+                    body.push(format!("return ({}, rb);", address));
+                    break;
+                }
+            };
+            body.push(format!("// {}", disassemble_from(program, address, 1)[0]));
+            if translate_instruction(&analysis, program, address, op, &mut body) {
+                break;
+            }
+            address += 1 + op.num_params as usize;
+            if analysis.block_starts.contains(&address) {
+                body.push(format!("pc = {};", address));
+                break;
+            }
+        }
+        lines.extend(body.into_iter().map(|line| format!("                {}", line)));
+        lines.push("            }".to_string());
+    }
+
+    lines.extend(
+        [
+            "            _ => return (pc, rb),",
+            "        }",
+            "    }",
+            "}",
+        ]
+        .iter()
+        .map(|line| line.to_string()),
+    );
+    lines.join("\n") + "\n"
+}
+
+impl<I: InputSource, O: OutputSink, M: Memory> Intcode<I, O, M> {
+    /// Runs a program with code produced by `translate`, handing over to the
+    /// interpreter wherever the translated code stops.
+    ///
+    /// The translated code is only used from a fresh start, with the standard
+    /// instruction set, and without any watchpoints, tracing or history;
+    /// otherwise this is the same as `run`. It's also skipped if something has
+    /// already been written at or beyond `MEMORY_LIMIT`, as it would need a
+    /// dense copy of everything up to there.
+    pub fn run_translated(&mut self, code: Translated) -> Result<(), IntcodeError> {
+        let fresh = self.pc == 0 && self.relative_base == 0 && self.program.extent() <= MEMORY_LIMIT as usize;
+        let standard = Arc::ptr_eq(&self.isa, standard_set());
+        if fresh && standard && self.watchpoints.is_empty() && self.trace.is_none() && self.history.is_none() {
+            let mut memory = self.program.to_vec();
+            let (input, output) = (&mut self.input, &mut self.output);
+            let (pc, relative_base) = code(
+                &mut memory,
+                &mut || input.next_input(),
+                &mut |value| output.push_output(value),
+            );
+            self.take_over(&memory, pc, relative_base);
+        }
+
+        self.run()
+    }
+
+    /// Picks up the state translated code stopped in.
+    fn take_over(&mut self, memory: &[i64], pc: i64, relative_base: i64) {
+        for (address, &value) in memory.iter().enumerate() {
+            if self.program.load(address) != value {
+                self.program.store(address, value);
+            }
+        }
+        self.pc = pc;
+        self.relative_base = relative_base;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    /// The programs from the tests for the VM and each day, with some input.
+    const CASES: &[(&str, &[i64])] = &[
+        ("3,0,4,0,99", &[256]),
+        ("1105,2,77,99", &[]),
+        ("1105,0,77,99", &[]),
+        ("1106,0,77,99", &[]),
+        ("1106,2,77,99", &[]),
+        ("104,5,1101,0,99,0,1105,1,0", &[]),
+        ("104,5,99", &[]),
+        ("1,9,10,3,2,3,11,0,99,30,40,50", &[]),
+        ("109,1000000000000,21101,3,4,0,204,0,99", &[]),
+        ("1,0,0,7,99", &[]),
+        ("1102,9223372036854775807,2,0,99", &[]),
+        ("1,0,0,0,42", &[]),
+        ("304,0,99", &[]),
+        ("10001,0,0,0,99", &[]),
+        ("1,-1,0,0,99", &[]),
+        ("3,0,99", &[]),
+        ("1,0,0,0,99", &[]),
+        ("3,9,8,9,10,9,4,9,99,-1,8", &[8]),
+        ("3,9,7,9,10,9,4,9,99,-1,8", &[7]),
+        ("3,3,1108,-1,8,3,4,3,99", &[8]),
+        ("3,3,1107,-1,8,3,4,3,99", &[9]),
+        (
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+            &[7],
+        ),
+        ("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0", &[4, 0]),
+        ("3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0", &[0, 0]),
+        (
+            "3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0",
+            &[1, 0],
+        ),
+        ("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5", &[9, 0]),
+        ("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99", &[]),
+        ("1102,34915192,34915192,7,4,7,99,0", &[]),
+        ("104,1125899906842624,99", &[]),
+        ("203,1,99", &[42]),
+        ("1,0,0,0,1,0,0,0,4,0,99", &[]),
+        ("1101,0,7,20,1105,1,9,99,0,104,5,106,0,20", &[]),
+        ("3,0,1,0,0,0,4,0,99", &[3]),
+        ("1,0,0,0,1,0,0,0,1101,1,1,20,99", &[]),
+        ("3,100,99", &[]),
+        ("3,0,4,0,109,7,3,1,4,1,99", &[4, 5]),
+    ];
+
+    /// Parses a Vec printed with `{:?}`.
+    fn parse_list(text: &str) -> Vec<i64> {
+        let text = text.trim_start_matches('[').trim_end_matches(']');
+        text.split(", ").filter(|s| !s.is_empty()).map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn translated_code_matches_interpreter() {
+        init();
+
+        // Build one program running every case, which prints where the
+        // translated code stopped and the state it left behind.
+        let mut source = String::new();
+        let mut main = vec!["fn main() {".to_string()];
+        for (ii, (program, inputs)) in CASES.iter().enumerate() {
+            let image = Intcode::from(program).unwrap().program.to_vec();
+            source.push_str(&translate(&image, &format!("case_{}", ii)));
+            main.push(format!(
+                "    {{
+        let mut memory = vec!{:?};
+        let mut inputs = vec!{:?}.into_iter();
+        let mut consumed = 0;
+        let mut outputs = Vec::new();
+        let (pc, rb) = case_{}(
+            &mut memory,
+            &mut || {{ let v = inputs.next(); consumed += v.is_some() as usize; v }},
+            &mut |v| outputs.push(v),
+        );
+        println!(\"{{}}|{{}}|{{}}|{{:?}}|{{:?}}\", pc, rb, consumed, outputs, memory);
+    }}",
+                image, inputs, ii
+            ));
+        }
+        main.push("}".to_string());
+        source.push_str(&main.join("\n"));
+
+        let dir = std::env::temp_dir().join(format!("intcode-translate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cases.rs"), source).unwrap();
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let status = Command::new(rustc)
+            .args(["--edition", "2018", "-o"])
+            .arg(dir.join("cases"))
+            .arg(dir.join("cases.rs"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "generated code didn't compile");
+        let results = Command::new(dir.join("cases")).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(results.status.success());
+        let results = String::from_utf8(results.stdout).unwrap();
+
+        for ((program, inputs), line) in CASES.iter().zip(results.lines()) {
+            let mut expected = Intcode::from(program).unwrap();
+            expected.input.extend(inputs.iter());
+            let expected_result = expected.run();
+
+            // Pick up where the translated code left off.
+            let fields: Vec<&str> = line.split('|').collect();
+            let mut actual = Intcode::from(program).unwrap();
+            actual.input.extend(inputs.iter().skip(fields[2].parse().unwrap()));
+            actual.output = parse_list(fields[3]);
+            actual.take_over(&parse_list(fields[4]), fields[0].parse().unwrap(), fields[1].parse().unwrap());
+            let actual_result = actual.run();
+
+            assert_eq!(expected_result, actual_result, "{}", program);
+            assert_eq!(expected.output, actual.output, "{}", program);
+            assert_eq!(expected.program, actual.program, "{}", program);
+        }
+
+        // The quine runs all the way to its halt without help.
+        let quine = CASES.iter().position(|(program, _)| program.starts_with("109,1,204")).unwrap();
+        assert!(results.lines().nth(quine).unwrap().starts_with("15|"));
+    }
+
+    #[test]
+    fn finds_blocks_and_code() {
+        init();
+        // Jumps over some data to a loop counting down from 3.
+        let image = Intcode::from("1105,1,5,7,7,1001,3,-1,3,1005,3,5,99").unwrap().program.to_vec();
+        let analysis = Analysis::new(&image);
+        assert_eq!(vec![0, 5, 12], analysis.block_starts.iter().copied().collect::<Vec<_>>());
+        assert_eq!(vec![(0, 2), (5, 12)], analysis.code_ranges());
+        assert!(!analysis.computed_jumps);
+        assert!(analysis.writable(3));
+        assert!(!analysis.writable(6));
+    }
+
+    #[test]
+    fn follows_pushed_return_addresses() {
+        init();
+        // Calls a subroutine at 9 which returns through the stack.
+        let image = Intcode::from("109,20,21101,9,0,0,1105,1,10,99,104,1,2106,0,0")
+            .unwrap()
+            .program
+            .to_vec();
+        let analysis = Analysis::new(&image);
+        assert!(analysis.computed_jumps);
+        assert!(analysis.block_starts.contains(&9));
+    }
+
+    #[test]
+    fn skips_translation_for_huge_memory() {
+        init();
+        fn unreachable(
+            _: &mut Vec<i64>,
+            _: &mut dyn FnMut() -> Option<i64>,
+            _: &mut dyn FnMut(i64),
+        ) -> (i64, i64) {
+            panic!("translated code ran");
+        }
+        let mut program = Intcode::from("104,5,99").unwrap();
+        program.mem_set(1_000_000_000_000, 1).unwrap();
+        assert_eq!(Ok(()), program.run_translated(unreachable));
+        assert_eq!(vec![5], program.output);
+    }
+
+    #[test]
+    fn skips_translation_for_other_instruction_sets() {
+        init();
+        fn unreachable(
+            _: &mut Vec<i64>,
+            _: &mut dyn FnMut() -> Option<i64>,
+            _: &mut dyn FnMut(i64),
+        ) -> (i64, i64) {
+            panic!("translated code ran");
+        }
+        // Day 5 didn't have the relative base, so the interpreter rejects it.
+        let mut program = Intcode::from("109,1,99").unwrap();
+        program.set_instruction_set(crate::intcode::InstructionSet::day5());
+        let result = program.run_translated(unreachable);
+        assert!(matches!(result, Err(IntcodeError::UnsupportedOpcode { pc: 0, .. })), "{:?}", result);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Whether memory was read or written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Details of a watchpoint firing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchHit {
+    /// The instruction which made the access.
+    pub pc: i64,
+    pub address: i64,
+    pub access: Access,
+    /// The value read, or the value written.
+    pub value: i64,
+}
+
+/// Stops execution when an address is read or written by an instruction.
+///
+/// Only parameter reads and write targets count: fetching an instruction
+/// doesn't trigger a read watchpoint on its own cells.
+#[derive(Clone)]
+pub struct Watchpoint {
+    pub address: i64,
+    on_read: bool,
+    on_write: bool,
+    predicate: Option<Arc<dyn Fn(i64) -> bool + Send + Sync>>,
+}
+
+impl fmt::Debug for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchpoint")
+            .field("address", &self.address)
+            .field("on_read", &self.on_read)
+            .field("on_write", &self.on_write)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+impl Watchpoint {
+    /// Fires when the address is read.
+    pub fn read(address: i64) -> Self {
+        Self {
+            address,
+            on_read: true,
+            on_write: false,
+            predicate: None,
+        }
+    }
+
+    /// Fires when the address is written.
+    pub fn write(address: i64) -> Self {
+        Self {
+            address,
+            on_read: false,
+            on_write: true,
+            predicate: None,
+        }
+    }
+
+    /// Fires when the address is read or written.
+    pub fn access(address: i64) -> Self {
+        Self {
+            address,
+            on_read: true,
+            on_write: true,
+            predicate: None,
+        }
+    }
+
+    /// Only fire when the value read or written matches the predicate.
+    pub fn when<F: Fn(i64) -> bool + Send + Sync + 'static>(mut self, predicate: F) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    pub fn matches(&self, address: i64, access: Access, value: i64) -> bool {
+        let wanted = match access {
+            Access::Read => self.on_read,
+            Access::Write => self.on_write,
+        };
+
+        wanted
+            && address == self.address
+            && self.predicate.as_ref().is_none_or(|predicate| predicate(value))
+    }
+}
@@ -0,0 +1,314 @@
+//! A variant of the VM which is generic over the type of its cells, so that
+//! programs can work with numbers bigger than an i64.
+//!
+//! Arithmetic is always checked, so an overflow is reported as an error
+//! rather than producing garbage. Addresses, jump targets and relative base
+//! adjustments still need to fit in an i64.
+//!
+//! This is a plain interpreter: watchpoints, tracing and the rest are only
+//! available on the main `Intcode` machine.
+
+use super::bigint::BigInt;
+use super::memory::PagedMemory;
+use super::{offset_address, IntcodeError, Opcode, Operation, ParamMode, StepResult};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+/// A type that can be used for the cells of a WideIntcode machine. Its default
+/// value must be zero, as that's what unwritten memory holds.
+pub trait Cell: Clone + Debug + Default + PartialEq + PartialOrd + FromStr {
+    fn from_i64(value: i64) -> Self;
+    fn to_i64(&self) -> Option<i64>;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+}
+
+impl Cell for i64 {
+    fn from_i64(value: i64) -> Self {
+        value
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        i64::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        i64::checked_mul(*self, *other)
+    }
+}
+
+impl Cell for i128 {
+    fn from_i64(value: i64) -> Self {
+        value.into()
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(*self).ok()
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        i128::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        i128::checked_mul(*self, *other)
+    }
+}
+
+impl Cell for BigInt {
+    fn from_i64(value: i64) -> Self {
+        value.into()
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        BigInt::to_i64(self)
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+}
+
+/// An Intcode machine whose cells are of type C.
+#[derive(Debug, Clone)]
+pub struct WideIntcode<C> {
+    pub program: PagedMemory<C>,
+    pub input: VecDeque<C>,
+    pub output: Vec<C>,
+    pc: i64,
+    relative_base: i64,
+}
+
+impl<C: Cell> WideIntcode<C> {
+    pub fn from(input: &str) -> Result<Self, IntcodeError> {
+        let program: Vec<C> = input
+            .trim()
+            .split(',')
+            .enumerate()
+            .map(|(position, s)| {
+                s.trim().parse().map_err(|_| IntcodeError::ParseError {
+                    position,
+                    entry: s.to_string(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            program: program.into(),
+            input: VecDeque::new(),
+            output: Vec::new(),
+            pc: 0,
+            relative_base: 0,
+        })
+    }
+
+    pub fn pc(&self) -> i64 {
+        self.pc
+    }
+
+    /// Converts a cell into an i64, for use as an address, jump target or
+    /// relative base adjustment.
+    fn small(&self, value: &C) -> Result<i64, IntcodeError> {
+        value.to_i64().ok_or(IntcodeError::Overflow { pc: self.pc })
+    }
+
+    pub fn mem_get(&self, address: i64) -> Result<C, IntcodeError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress { pc: self.pc, address });
+        }
+        Ok(self.program.get(address as usize).cloned().unwrap_or_default())
+    }
+
+    fn mem_set(&mut self, address: i64, value: C) -> Result<(), IntcodeError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress { pc: self.pc, address });
+        }
+        *self.program.get_mut(address as usize) = value;
+        Ok(())
+    }
+
+    /// Works out the parameters for an operation. Values to be read are
+    /// looked up; write targets are given as addresses.
+    fn get_params(&self, op: &Operation) -> Result<Vec<C>, IntcodeError> {
+        let mut params = Vec::new();
+
+        for (ii, mode) in op.param_modes().iter().enumerate() {
+            let raw = self.mem_get(offset_address(self.pc, ii as i64 + 1, self.pc)?)?;
+            params.push(match mode {
+                ParamMode::Immediate | ParamMode::Reference => raw,
+                ParamMode::Position => self.mem_get(self.small(&raw)?)?,
+                ParamMode::Relative => {
+                    self.mem_get(offset_address(self.small(&raw)?, self.relative_base, self.pc)?)?
+                }
+                ParamMode::RelativeReference => {
+                    C::from_i64(offset_address(self.small(&raw)?, self.relative_base, self.pc)?)
+                }
+            });
+        }
+
+        Ok(params)
+    }
+
+    /// Perform a single operation, in the same way as `Intcode::step`.
+    ///
+    /// Only the standard instruction set is supported, as other instructions
+    /// are defined on i64s rather than cells.
+    pub fn step(&mut self) -> Result<StepResult, IntcodeError> {
+        let instruction = self.mem_get(self.pc)?;
+        let code = self.small(&instruction)?;
+        let op = Operation::decode(code, self.pc)?;
+        let params = self.get_params(&op)?;
+        let overflow = IntcodeError::Overflow { pc: self.pc };
+
+        let mut result = StepResult::Continue;
+        let mut pc_moved = false;
+
+        match op.opcode {
+            Opcode::Add => {
+                let sum = params[0].checked_add(&params[1]).ok_or(overflow)?;
+                self.mem_set(self.small(&params[2])?, sum)?;
+            }
+            Opcode::Multiply => {
+                let product = params[0].checked_mul(&params[1]).ok_or(overflow)?;
+                self.mem_set(self.small(&params[2])?, product)?;
+            }
+            Opcode::StoreInput => {
+                let input = match self.input.pop_front() {
+                    Some(input) => input,
+                    None => return Ok(StepResult::NeedInput),
+                };
+                self.mem_set(self.small(&params[0])?, input)?;
+            }
+            Opcode::PushOutput => {
+                self.output.push(params[0].clone());
+                result = StepResult::Output;
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let zero = params[0] == C::from_i64(0);
+                if zero == (op.opcode == Opcode::JumpIfFalse) {
+                    self.pc = self.small(&params[1])?;
+                    pc_moved = true;
+                }
+            }
+            Opcode::LessThan | Opcode::Equals => {
+                let condition = if op.opcode == Opcode::LessThan {
+                    params[0] < params[1]
+                } else {
+                    params[0] == params[1]
+                };
+                let value = C::from_i64(if condition { 1 } else { 0 });
+                self.mem_set(self.small(&params[2])?, value)?;
+            }
+            Opcode::UpdateBase => {
+                let adjustment = self.small(&params[0])?;
+                self.relative_base = self.relative_base.checked_add(adjustment).ok_or(overflow)?;
+            }
+            Opcode::Halt => {
+                result = StepResult::Halt;
+            }
+            Opcode::Custom => return Err(IntcodeError::UnknownOpcode { pc: self.pc, opcode: code }),
+        }
+
+        if !pc_moved {
+            self.pc = offset_address(self.pc, op.num_params + 1, self.pc)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Runs step-by-step until it encounters a Halt. Running out of input is
+    /// an error here.
+    pub fn run(&mut self) -> Result<(), IntcodeError> {
+        loop {
+            match self.step()? {
+                StepResult::Halt => return Ok(()),
+                StepResult::NeedInput => return Err(IntcodeError::InputExhausted { pc: self.pc }),
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs step-by-step until the program wants input that isn't there yet,
+    /// or halts.
+    pub fn run_until_input_or_halt(&mut self) -> Result<StepResult, IntcodeError> {
+        loop {
+            match self.step()? {
+                StepResult::Continue | StepResult::Output => {}
+                result => return Ok(result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // Squares 34915192 three times over, then outputs the result.
+    const SQUARES: &str = "1102,34915192,34915192,20,2,20,20,20,2,20,20,20,4,20,99,0,0,0,0,0,0";
+
+    #[test]
+    fn overflow_is_detected() {
+        init();
+        let mut program = WideIntcode::<i64>::from(SQUARES).unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: 4 }), program.run());
+
+        let mut program = WideIntcode::<i128>::from(SQUARES).unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: 8 }), program.run());
+
+        let mut program = WideIntcode::<i64>::from("109,1,201,9223372036854775807,0,0,99").unwrap();
+        assert_eq!(Err(IntcodeError::Overflow { pc: 2 }), program.run());
+    }
+
+    #[test]
+    fn bignum_results() {
+        init();
+        let mut program = WideIntcode::<BigInt>::from(SQUARES).unwrap();
+        program.run().unwrap();
+        assert_eq!(
+            "2208591908271884275716666044391205095685624913057165413974016",
+            program.output[0].to_string()
+        );
+    }
+
+    #[test]
+    fn matches_i64_machine() {
+        init();
+        let mut program =
+            WideIntcode::<BigInt>::from("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99")
+                .unwrap();
+        program.run().unwrap();
+        let output: Vec<String> = program.output.iter().map(|c| c.to_string()).collect();
+        assert_eq!("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99", output.join(","));
+
+        let mut program = WideIntcode::<i128>::from("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        program.input.push_back(8);
+        program.run().unwrap();
+        assert_eq!(vec![1], program.output);
+    }
+
+    #[test]
+    fn huge_addresses() {
+        init();
+        // Writes 7 a trillion cells up, then reads it back.
+        let mut program = WideIntcode::<BigInt>::from("109,1000000000000,21101,3,4,0,204,0,99").unwrap();
+        program.run().unwrap();
+        assert_eq!(vec![BigInt::from(7)], program.output);
+        assert_eq!(BigInt::from(7), program.mem_get(1_000_000_000_000).unwrap());
+        assert_eq!(BigInt::default(), program.mem_get(999_999_999_999).unwrap());
+    }
+}
@@ -0,0 +1,6 @@
+//! The Intcode VM and its tools, shared by the days that use it.
+
+#[macro_use]
+extern crate log;
+
+pub mod intcode;
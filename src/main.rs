@@ -2,7 +2,10 @@
 extern crate log;
 extern crate env_logger;
 
-// mod intcode;
+// The intcode VM lives in the library, shared by the tools and the days that
+// use it.
+use advent_of_code::intcode;
+
 // mod one;
 // mod two;
 // mod three;
@@ -12,10 +15,20 @@ extern crate env_logger;
 // mod seven;
 mod eight;
 // mod nine;
+mod tools;
 
 fn main() {
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => solve(),
+        Some(command) => tools::run(command, &args[1..]),
+    }
+}
+
+/// Solve the puzzles.
+fn solve() {
     // debug!("Start problem 1");
     // println!("Problem 1a: {}", one::part_a());
     // println!("Problem 1b: {}", one::part_b());
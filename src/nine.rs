@@ -1,18 +1,18 @@
 use crate::intcode::*;
 
 pub fn part_a() -> i64 {
-    let mut program = Intcode::from_file("input9.txt");
-    program.input.push(1);
-    program.run();
+    let mut program = Intcode::from_file("input9.txt").expect("Invalid program");
+    program.input.push_back(1);
+    program.run().expect("Program failed");
     debug!("output: {:?}", program.output);
 
     program.output[0]
 }
 
 pub fn part_b() -> i64 {
-    let mut program = Intcode::from_file("input9.txt");
-    program.input.push(2);
-    program.run();
+    let mut program = Intcode::from_file("input9.txt").expect("Invalid program");
+    program.input.push_back(2);
+    program.run().expect("Program failed");
     debug!("output: {:?}", program.output);
 
     program.output[0]
@@ -29,17 +29,17 @@ mod tests {
     #[test]
     fn nine_example_one() {
         init();
-        let mut program = Intcode::from("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99");
+        let mut program = Intcode::from("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99").unwrap();
         let output = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
-        program.run();
+        program.run().unwrap();
         assert_eq!(output, program.output);
     }
 
     #[test]
     fn nine_example_two() {
         init();
-        let mut program = Intcode::from("1102,34915192,34915192,7,4,7,99,0");
-        program.run();
+        let mut program = Intcode::from("1102,34915192,34915192,7,4,7,99,0").unwrap();
+        program.run().unwrap();
         let output = program.output[0].to_string();
         assert_eq!(16, output.chars().count());
     }
@@ -47,17 +47,17 @@ mod tests {
     #[test]
     fn nine_example_three() {
         init();
-        let mut program = Intcode::from("104,1125899906842624,99");
-        program.run();
+        let mut program = Intcode::from("104,1125899906842624,99").unwrap();
+        program.run().unwrap();
         assert_eq!(1125899906842624, program.output[0]);
     }
 
     #[test]
     fn example_203() {
         init();
-        let mut program = Intcode::from("203,1,99");
-        program.input.push(5);
-        program.run();
+        let mut program = Intcode::from("203,1,99").unwrap();
+        program.input.push_back(5);
+        program.run().unwrap();
         assert_eq!(5, program.program[1]);
     }
 }
\ No newline at end of file
@@ -1,13 +1,15 @@
+use crate::intcode::network::*;
+use crate::intcode::search::*;
 use crate::intcode::*;
 use itertools::Itertools;
 
 fn amplifier(setting: i64, input: i64, mut program: Intcode) -> i64 {
     // Set up the input.
-    program.input.push(setting);
-    program.input.push(input);
+    program.input.push_back(setting);
+    program.input.push_back(input);
 
     // Run the program.
-    program.run();
+    program.run().expect("Program failed");
 
     // Retrieve the output.
     program.output[0]
@@ -26,51 +28,37 @@ fn five_amplifiers(settings: Vec<i64>, program: Intcode) -> i64 {
 
 fn loop_thrusters(digits: Vec<i64>, program: Intcode) -> i64 {
     debug!("{:?}", digits);
-    let mut programs = vec![program.clone(); 5];
-    for i in 0..5 {
-        programs[i].input.push(digits[i]);
+    let mut network = Network::copies(&program, 5, Topology::Ring);
+    for (i, &digit) in digits.iter().enumerate() {
+        network.send(i, digit);
     }
-    let mut input = 0;
-    for j in (0..5).cycle() {
-        programs[j].input.push(input);
-        match programs[j].run_until_output() {
-            StepResult::Output => {
-                input = programs[j].output.pop().unwrap();
-            }
-            StepResult::Halt => return input,
-            StepResult::Continue => unreachable!(),
-        }
+    network.send(0, 0);
+
+    match network.run().expect("Program failed") {
+        NetworkState::Halted => network.last_output[4].expect("No thrust"),
+        NetworkState::Idle => panic!("Amplifiers deadlocked"),
     }
-    unreachable!()
 }
 
 pub fn part_a() -> i64 {
-    let options = (0..5).permutations(5);
-
-    let program = Intcode::from_file("input7.txt");
-
-    let mut top_option = 0;
+    let program = Intcode::from_file("input7.txt").expect("Invalid program");
 
-    for option in options {
+    let best = find_max_with((0..5).permutations(5), |option| {
         debug!("{:?}", option);
-        let output = five_amplifiers(option, program.clone());
-        debug!("output: {}", output);
+        Some(five_amplifiers(option.clone(), program.clone()))
+    });
 
-        if output > top_option {
-            top_option = output;
-        }
-    }
-
-    top_option
+    best.map(|(_, output)| output).unwrap()
 }
 
 pub fn part_b() -> i64 {
-    let options = (5..10).permutations(5);
+    let program = Intcode::from_file("input7.txt").expect("Invalid program");
+
+    let best = find_max_with((5..10).permutations(5), |digits| {
+        Some(loop_thrusters(digits.clone(), program.clone()))
+    });
 
-    options
-        .map(|digits| loop_thrusters(digits, Intcode::from_file("input7.txt")))
-        .max()
-        .unwrap()
+    best.map(|(_, output)| output).unwrap()
 }
 
 #[cfg(test)]
@@ -85,7 +73,7 @@ mod tests {
     fn seven_example_one() {
         init();
 
-        let program = Intcode::from("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+        let program = Intcode::from("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0").unwrap();
 
         assert_eq!(43210, five_amplifiers(vec![4, 3, 2, 1, 0], program));
     }
@@ -96,7 +84,7 @@ mod tests {
 
         let program = Intcode::from(
             "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0",
-        );
+        ).unwrap();
 
         assert_eq!(54321, five_amplifiers(vec![0, 1, 2, 3, 4], program));
     }
@@ -105,7 +93,7 @@ mod tests {
     fn seven_example_three() {
         init();
 
-        let program = Intcode::from("3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0");
+        let program = Intcode::from("3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0").unwrap();
 
         assert_eq!(65210, five_amplifiers(vec![1, 0, 4, 3, 2], program));
     }
@@ -115,7 +103,7 @@ mod tests {
         init();
         let program = Intcode::from(
             "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
-        );
+        ).unwrap();
         assert_eq!(139629729, loop_thrusters(vec![9, 8, 7, 6, 5], program));
     }
 }
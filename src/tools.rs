@@ -0,0 +1,504 @@
+//! Command line tools for working with Intcode programs, run as
+//! `advent-of-code <command> [args...]`.
+
+use crate::intcode::debugger::Stop;
+use crate::intcode::trace::{first_difference, read_trace, write_trace};
+use crate::intcode::*;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: advent-of-code [command] [args...]
+
+With no command, solves the puzzles. Wherever a program is expected, a saved
+snapshot can be used instead. Commands:
+  ascii <file> [commands]
+                   talk to a program that speaks ASCII, typing commands at
+                   the terminal or taking them from a file, one per line
+  asm <file>       assemble a source file into an Intcode program
+  bench [repeats]  time the VM on the day 2, 7 and 9 inputs
+  cfg <file>       print the control-flow graph of a program in DOT format
+  coverage <file> [input...]
+                   run a program, then show which addresses it used
+  covdiff <file> <inputs> <inputs>
+                   compare the instructions run under two comma-separated
+                   lists of inputs
+  debug <file>     run an Intcode program under the interactive debugger
+  disasm <file>    print a disassembly of an Intcode program
+  lint <file>      check a program for problems without running it
+  profile <file> [input...]
+                   run a program, then report where the time went
+  trace <file> [input...]
+                   run a program, printing a trace of each instruction
+  tracediff <trace> <trace>
+                   show where two saved traces first differ
+  translate <file> [name]
+                   translate an Intcode program into a Rust function";
+
+/// Runs the named tool, exiting with an error if it isn't one we know about.
+pub fn run(command: &str, args: &[String]) {
+    match command {
+        "ascii" => ascii(args),
+        "asm" => asm(args),
+        "bench" => bench(args),
+        "cfg" => cfg(args),
+        "coverage" => coverage(args),
+        "covdiff" => covdiff(args),
+        "debug" => debug(args),
+        "disasm" => disasm(args),
+        "lint" => lint_program(args),
+        "profile" => profile(args),
+        "trace" => trace(args),
+        "tracediff" => tracediff(args),
+        "translate" => translate(args),
+        _ => fail(USAGE),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Loads the program (or snapshot) named by the first argument.
+fn load(args: &[String]) -> Intcode {
+    let name = args.first().unwrap_or_else(|| fail(USAGE));
+    let data = std::fs::read_to_string(name).unwrap_or_else(|e| fail(&format!("{}: {}", name, e)));
+    let program = if snapshot::is_snapshot(&data) {
+        Intcode::from_snapshot(&data)
+    } else {
+        Intcode::from(&data)
+    };
+
+    program.unwrap_or_else(|e| fail(&format!("{}: {}", name, e)))
+}
+
+fn ascii(args: &[String]) {
+    let mut ascii = Ascii::new(load(args));
+    let stdout = io::stdout();
+    let result = match args.get(1) {
+        Some(name) => {
+            let file = std::fs::File::open(name).unwrap_or_else(|e| fail(&format!("{}: {}", name, e)));
+            ascii.interact(io::BufReader::new(file), stdout.lock(), true)
+        }
+        None => ascii.interact(io::stdin().lock(), stdout.lock(), false),
+    };
+    match result {
+        Ok(StepResult::Halt) => {}
+        Ok(StepResult::NeedInput) => println!("\n(out of commands)"),
+        Ok(result) => println!("\n(stopped: {:?})", result),
+        Err(e) => fail(&format!("Program failed: {}", e)),
+    }
+}
+
+fn asm(args: &[String]) {
+    let name = args.first().unwrap_or_else(|| fail(USAGE));
+    let source = std::fs::read_to_string(name).unwrap_or_else(|e| fail(&format!("{}: {}", name, e)));
+
+    match assemble(&source) {
+        Ok(program) => println!("{}", program),
+        Err(e) => fail(&format!("{}: {}", name, e)),
+    }
+}
+
+fn cfg(args: &[String]) {
+    let program = load(args);
+
+    print!("{}", cfg::Cfg::new(&program.program.to_vec()).to_dot());
+}
+
+fn disasm(args: &[String]) {
+    let program = load(args);
+
+    for line in disassemble(&program.program.to_vec()) {
+        println!("{}", line);
+    }
+}
+
+/// Lists any problems the linter finds, failing if any of them are errors.
+fn lint_program(args: &[String]) {
+    let diagnostics: Vec<Diagnostic> = lint(&load(args).program.to_vec());
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    if diagnostics.is_empty() {
+        println!("No problems found");
+    } else if diagnostics.iter().any(|d| d.problem.severity() == lint::Severity::Error) {
+        process::exit(1);
+    }
+}
+
+/// A benchmark, run against a freshly loaded program.
+type Workload = fn(&Intcode);
+
+/// Runs every noun and verb for day 2.
+fn bench_two(base: &Intcode) {
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut program = base.clone();
+            program.program[1] = noun;
+            program.program[2] = verb;
+            program.run().expect("Program failed");
+        }
+    }
+}
+
+/// Runs every phase setting for day 7, in both series and feedback mode.
+fn bench_seven(base: &Intcode) {
+    use crate::intcode::network::{Network, Topology};
+    use itertools::Itertools;
+
+    for settings in (0..5).permutations(5) {
+        let mut value = 0;
+        for setting in settings {
+            let mut program = base.clone();
+            program.input.extend(vec![setting, value]);
+            program.run().expect("Program failed");
+            value = program.output[0];
+        }
+    }
+    for settings in (5..10).permutations(5) {
+        let mut network = Network::copies(base, 5, Topology::Ring);
+        for (ii, setting) in settings.into_iter().enumerate() {
+            network.send(ii, setting);
+        }
+        network.send(0, 0);
+        network.run().expect("Program failed");
+    }
+}
+
+/// Runs the day 9 BOOST program in sensor boost mode.
+fn bench_nine(base: &Intcode) {
+    let mut program = base.clone();
+    program.input.push_back(2);
+    program.run().expect("Program failed");
+}
+
+/// Times each workload with the old allocating decoder, the new one, and the
+/// new one with the decode cache, taking the best of a few runs.
+fn bench(args: &[String]) {
+    let repeats = match args.first() {
+        Some(arg) => arg.parse().unwrap_or_else(|_| fail(USAGE)),
+        None => 5,
+    };
+    let workloads: [(&str, &str, Workload); 3] = [
+        ("day 2", "input2.txt", bench_two),
+        ("day 7", "input7.txt", bench_seven),
+        ("day 9", "input9.txt", bench_nine),
+    ];
+
+    for (name, file, workload) in workloads.iter() {
+        let mut base =
+            Intcode::from_file(file).unwrap_or_else(|e| fail(&format!("{}: {}", file, e)));
+        let time = |base: &Intcode| {
+            let mut best = Duration::MAX;
+            for _ in 0..repeats {
+                let start = Instant::now();
+                workload(base);
+                best = best.min(start.elapsed());
+            }
+            best.as_secs_f64() * 1000.0
+        };
+
+        base.set_decoder(Decoder::Allocating);
+        let allocating = time(&base);
+        base.set_decoder(Decoder::Fresh);
+        let fresh = time(&base);
+        base.set_decoder(Decoder::Cached);
+        let cached = time(&base);
+        println!(
+            "{}: {:>8.2}ms allocating, {:>8.2}ms fresh ({:.1}x), {:>8.2}ms cached ({:.1}x)",
+            name,
+            allocating,
+            fresh,
+            allocating / fresh,
+            cached,
+            allocating / cached
+        );
+    }
+}
+
+/// Parses a single input value.
+fn input(arg: &str) -> i64 {
+    arg.parse().unwrap_or_else(|_| fail(&format!("Invalid input: {}", arg)))
+}
+
+/// Parses the arguments after the program name as input values.
+fn inputs(args: &[String]) -> Vec<i64> {
+    args.iter().skip(1).map(|arg| input(arg)).collect()
+}
+
+/// Runs a copy of a program with some input, measuring its coverage.
+fn run_with_coverage(program: &Intcode, input: Vec<i64>) -> Coverage {
+    let mut program = program.clone();
+    program.input.extend(input);
+    program.start_coverage();
+    if let Err(e) = program.run() {
+        eprintln!("{}", e);
+    }
+    program.take_coverage().unwrap_or_default()
+}
+
+fn coverage(args: &[String]) {
+    let program = load(args);
+    let image = program.program.image().to_vec();
+    let coverage = run_with_coverage(&program, inputs(args));
+
+    println!("{}\n", coverage.summary());
+    print!("{}", coverage.map(image.len()));
+    let beyond = coverage.beyond(image.len());
+    if !beyond.is_empty() {
+        print!("\nBeyond the end of the program:\n{}", beyond);
+    }
+    print!("\n{}", coverage.annotate(program.instruction_set(), &image));
+}
+
+fn covdiff(args: &[String]) {
+    if args.len() != 3 {
+        fail(USAGE);
+    }
+    let program = load(args);
+    let image = program.program.image().to_vec();
+    let parse = |list: &String| list.split(',').map(input).collect();
+    let a = run_with_coverage(&program, parse(&args[1]));
+    let b = run_with_coverage(&program, parse(&args[2]));
+
+    for (name, here, other) in [(&args[1], &a, &b), (&args[2], &b, &a)].iter() {
+        println!("Only with input {}:", name);
+        for line in here.executed_only_here(other, program.instruction_set(), &image) {
+            println!("    {}", line);
+        }
+    }
+}
+
+fn profile(args: &[String]) {
+    let mut program = load(args);
+    let image = program.program.image().to_vec();
+    program.input.extend(inputs(args));
+    program.start_profile();
+
+    let result = program.run();
+    println!("Output: {:?}\n", program.output);
+    if let Some(profile) = program.take_profile() {
+        print!("{}", profile.report(program.instruction_set(), &image, 20));
+    }
+    if let Err(e) = result {
+        fail(&e.to_string());
+    }
+}
+
+fn trace(args: &[String]) {
+    let mut program = load(args);
+    program.input.extend(inputs(args));
+    program.start_trace();
+
+    let result = program.run();
+    write_trace(&program.take_trace(), io::stdout().lock()).expect("Unable to write trace");
+    if let Err(e) = result {
+        fail(&e.to_string());
+    }
+}
+
+fn tracediff(args: &[String]) {
+    if args.len() != 2 {
+        fail(USAGE);
+    }
+    let load_trace = |name: &String| {
+        let file = std::fs::File::open(name).expect("Unable to open file");
+        read_trace(io::BufReader::new(file)).unwrap_or_else(|e| fail(&format!("{}: {}", name, e)))
+    };
+    let (a, b) = (load_trace(&args[0]), load_trace(&args[1]));
+
+    match first_difference(&a, &b) {
+        None => println!("Traces are identical ({} instructions)", a.len()),
+        Some(index) => {
+            println!("Traces differ at instruction {}", index);
+            for event in &a[index.saturating_sub(3)..index] {
+                println!("    {}", event);
+            }
+            let show = |event: Option<&TraceEvent>| match event {
+                Some(event) => event.to_string(),
+                None => "(end of trace)".to_string(),
+            };
+            println!("< {}", show(a.get(index)));
+            println!("> {}", show(b.get(index)));
+        }
+    }
+}
+
+fn translate(args: &[String]) {
+    let program = load(args);
+    let name = args.get(1).map_or("run_program", String::as_str);
+
+    print!("{}", translate::translate(&program.program.to_vec(), name));
+}
+
+const DEBUG_HELP: &str = "Commands:
+  b <addr>            set a breakpoint
+  d <addr>            delete a breakpoint
+  watch <addr> [v]    stop when addr is written (with value v)
+  rwatch <addr> [v]   stop when addr is read (with value v)
+  awatch <addr> [v]   stop when addr is read or written (with value v)
+  unwatch <addr>      delete watchpoints on addr
+  s [n]               step n instructions (default 1)
+  n                   step over the current instruction
+  c                   continue to the next breakpoint
+  back [n]            step n instructions backwards (default 1)
+  rewind <addr>       step backwards to the last write to addr
+  r                   show registers and breakpoints
+  l [n]               list n instructions from the pc (default 5)
+  x <addr> [n]        dump n cells of memory (default 1)
+  set <addr> <value>  write to memory
+  in <value>...       push input values
+  save <file>         save a snapshot of the machine
+  q                   quit";
+
+fn debug(args: &[String]) {
+    let mut debugger = Debugger::new(load(args));
+    debugger.machine.start_history();
+    let stdin = io::stdin();
+
+    print_listing(&debugger, 1);
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush().expect("Unable to write prompt");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).expect("Unable to read command") == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let ["save", name] = words[..] {
+            if let Err(e) = debugger.machine.save_snapshot(name) {
+                println!("{}: {}", name, e);
+            }
+            continue;
+        }
+        let numbers: Result<Vec<i64>, _> = words.iter().skip(1).map(|w| w.parse()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        let arg = |ii: usize, default: i64| *numbers.get(ii).unwrap_or(&default);
+
+        let stop = match words.first() {
+            None => continue,
+            Some(&"b") | Some(&"break") if !numbers.is_empty() => {
+                debugger.add_breakpoint(numbers[0]);
+                continue;
+            }
+            Some(&"d") | Some(&"delete") if !numbers.is_empty() => {
+                if !debugger.remove_breakpoint(numbers[0]) {
+                    println!("No breakpoint at {}", numbers[0]);
+                }
+                continue;
+            }
+            Some(&"watch") | Some(&"rwatch") | Some(&"awatch") if !numbers.is_empty() => {
+                let mut watchpoint = match words[0] {
+                    "watch" => Watchpoint::write(numbers[0]),
+                    "rwatch" => Watchpoint::read(numbers[0]),
+                    _ => Watchpoint::access(numbers[0]),
+                };
+                if let Some(&wanted) = numbers.get(1) {
+                    watchpoint = watchpoint.when(move |value| value == wanted);
+                }
+                debugger.add_watchpoint(watchpoint);
+                continue;
+            }
+            Some(&"unwatch") if !numbers.is_empty() => {
+                if !debugger.remove_watchpoints(numbers[0]) {
+                    println!("No watchpoint on {}", numbers[0]);
+                }
+                continue;
+            }
+            Some(&"s") | Some(&"step") => {
+                let mut stop = Ok(Stop::Stepped);
+                for _ in 0..arg(0, 1) {
+                    stop = debugger.step();
+                    if stop != Ok(Stop::Stepped) {
+                        break;
+                    }
+                }
+                stop
+            }
+            Some(&"n") | Some(&"next") => debugger.next(),
+            Some(&"c") | Some(&"continue") => debugger.cont(),
+            Some(&"back") => {
+                for _ in 0..arg(0, 1) {
+                    if !debugger.step_back() {
+                        println!("At start of history");
+                        break;
+                    }
+                }
+                Ok(Stop::Stepped)
+            }
+            Some(&"rewind") if !numbers.is_empty() => {
+                if !debugger.rewind_to_write(numbers[0]) {
+                    println!("No write to {} in history", numbers[0]);
+                }
+                Ok(Stop::Stepped)
+            }
+            Some(&"r") | Some(&"regs") => {
+                println!(
+                    "pc: {}  rb: {}  breakpoints: {:?}  watchpoints: {:?}",
+                    debugger.machine.pc(),
+                    debugger.machine.relative_base(),
+                    debugger.breakpoints().collect::<Vec<_>>(),
+                    debugger.machine.watchpoints()
+                );
+                continue;
+            }
+            Some(&"l") | Some(&"list") => {
+                print_listing(&debugger, arg(0, 5) as usize);
+                continue;
+            }
+            Some(&"x") if !numbers.is_empty() => {
+                match debugger.memory(numbers[0], arg(1, 1) as usize) {
+                    Ok(cells) => println!("{:04}: {:?}", numbers[0], cells),
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            Some(&"set") if numbers.len() == 2 => {
+                if let Err(e) = debugger.set_memory(numbers[0], numbers[1]) {
+                    println!("{}", e);
+                }
+                continue;
+            }
+            Some(&"in") => {
+                numbers.iter().for_each(|&value| debugger.push_input(value));
+                continue;
+            }
+            Some(&"q") | Some(&"quit") => break,
+            Some(_) => {
+                println!("{}", DEBUG_HELP);
+                continue;
+            }
+        };
+
+        for value in debugger.take_output() {
+            println!("output: {}", value);
+        }
+        match stop {
+            Ok(Stop::Stepped) => {}
+            Ok(Stop::Breakpoint(pc)) => println!("Breakpoint at {}", pc),
+            Ok(Stop::Watchpoint(hit)) => println!(
+                "Watchpoint: {:?} of {} at [{}] by pc {}",
+                hit.access, hit.value, hit.address, hit.pc
+            ),
+            Ok(Stop::NeedInput) => println!("Waiting for input"),
+            Ok(Stop::Halted) => println!("Halted"),
+            Err(e) => println!("Error: {}", e),
+        }
+        print_listing(&debugger, 1);
+    }
+}
+
+fn print_listing(debugger: &Debugger, count: usize) {
+    for line in debugger.listing(count) {
+        println!("{}", line);
+    }
+}
@@ -1,34 +1,40 @@
+use crate::intcode::search::*;
 use crate::intcode::*;
 
 pub fn part_a() -> i64 {
-    let mut program = Intcode::from_file("input2.txt");
+    let mut program = Intcode::from_file("input2.txt").expect("Invalid program");
+    program.set_instruction_set(InstructionSet::day2());
 
     program.program[1] = 12;
     program.program[2] = 2;
 
-    program.run();
+    program.run().expect("Program failed");
 
     program.program[0]
 }
 
 pub fn part_b() -> i64 {
-    let base = Intcode::from_file("input2.txt");
+    let mut base = Intcode::from_file("input2.txt").expect("Invalid program");
+    base.set_instruction_set(InstructionSet::day2());
+    let candidates = (0..100).flat_map(|noun| (0..100).map(move |verb| (noun, verb)));
 
-    for noun in 0..100 {
-        for verb in 0..100 {
-            let mut program = base.clone();
+    // The program only runs a handful of instructions, so anything still
+    // going after a thousand never will finish.
+    let found = find_first(
+        &base,
+        1000,
+        candidates,
+        |program, &(noun, verb)| {
             program.program[1] = noun;
             program.program[2] = verb;
+        },
+        |program| program.program[0] == 19690720,
+    );
 
-            program.run();
-
-            if program.program[0] == 19690720 {
-                return 100 * noun + verb;
-            }
-        }
+    match found {
+        Some((noun, verb)) => 100 * noun + verb,
+        None => -1,
     }
-
-    -1
 }
 
 #[cfg(test)]
@@ -42,8 +48,25 @@ mod tests {
     #[test]
     fn two_example_one() {
         init();
-        let mut program = Intcode::from("1,0,0,0,99");
-        program.run();
+        let mut program = Intcode::from("1,0,0,0,99").unwrap();
+        program.run().unwrap();
         assert_eq!(vec![2, 0, 0, 0, 99], program.program);
     }
+
+    #[test]
+    fn two_examples_at_day_2_level() {
+        init();
+        let examples = [
+            ("1,0,0,0,99", "2,0,0,0,99"),
+            ("2,3,0,3,99", "2,3,0,6,99"),
+            ("2,4,4,5,99,0", "2,4,4,5,99,9801"),
+            ("1,1,1,4,99,5,6,0,99", "30,1,1,4,2,5,6,0,99"),
+        ];
+        for &(source, expected) in &examples {
+            let mut program = Intcode::from(source).unwrap();
+            program.set_instruction_set(InstructionSet::day2());
+            program.run().unwrap();
+            assert_eq!(Intcode::from(expected).unwrap().program, program.program);
+        }
+    }
 }